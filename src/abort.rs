@@ -0,0 +1,305 @@
+//! The javascript-like `AbortController` and `AbortSignal` used to cancel a `fetch`.
+
+use crate::{blocking, Error, Result};
+use std::future::Future;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// Aborts one or more requests through its `AbortSignal`, mirroring the javascript
+/// `AbortController`.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{fetch_with, AbortController, RequestInit};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let controller = AbortController::new();
+/// let request = fetch_with("https://www.google.com/", RequestInit {
+///     signal: Some(controller.signal()),
+///     ..Default::default()
+/// });
+/// controller.abort("the user navigated away");
+/// assert!(matches!(request.await, Err(js_lib::Error::AbortError { .. })));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AbortController {
+    signal: AbortSignal
+}
+
+impl AbortController {
+    /// Creates a controller whose signal is not aborted.
+    pub fn new() -> AbortController {
+        AbortController::default()
+    }
+
+    /// The signal which is aborted by this controller.
+    pub fn signal(&self) -> AbortSignal {
+        self.signal.clone()
+    }
+
+    /// Aborts the signal with a reason, failing every request using it with `Error::AbortError`.
+    ///
+    /// **NOTE**: Only the first reason is kept, aborting an aborted signal does nothing.
+    pub fn abort(&self, reason: impl Into<String>) {
+        self.signal.inner.abort(Reason { message: reason.into(), timed_out: false });
+    }
+}
+
+/// Signals that a request should be aborted, mirroring the javascript `AbortSignal`.
+///
+/// Clones of a signal share the same state, so a signal can be handed to requests running
+/// on other tasks.
+#[derive(Debug, Clone, Default)]
+pub struct AbortSignal {
+    inner: Arc<Inner>
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    reason: Mutex<Option<Reason>>,
+    notify: Notify,
+    dependents: Mutex<Vec<Weak<Inner>>>
+}
+
+/// Why a signal was aborted, and whether it was by a timeout.
+#[derive(Debug, Clone)]
+struct Reason {
+    message: String,
+    timed_out: bool
+}
+
+impl Inner {
+    fn reason(&self) -> Option<Reason> {
+        self.reason.lock().unwrap().clone()
+    }
+
+    fn abort(&self, reason: Reason) {
+        {
+            let mut current = self.reason.lock().unwrap();
+            if current.is_some() {
+                return;
+            }
+            *current = Some(reason.clone());
+        }
+        self.notify.notify_waiters();
+        let dependents = std::mem::take(&mut *self.dependents.lock().unwrap());
+        for dependent in dependents.iter().filter_map(Weak::upgrade) {
+            dependent.abort(reason.clone());
+        }
+    }
+}
+
+impl AbortSignal {
+    /// Creates a signal which is already aborted with a reason.
+    pub fn abort(reason: impl Into<String>) -> AbortSignal {
+        let signal = AbortSignal::default();
+        signal.inner.abort(Reason { message: reason.into(), timed_out: false });
+        signal
+    }
+
+    /// Creates a signal which aborts itself after a number of milliseconds, failing every
+    /// request using it with `Error::TimeoutError`.
+    ///
+    /// **NOTE**: The timer runs on the current tokio runtime, or on the background runtime of the
+    /// `blocking` module when there is none.
+    pub fn timeout(ms: u64) -> AbortSignal {
+        let signal = AbortSignal::default();
+        let inner = signal.inner.clone();
+        let delay = Duration::from_millis(ms);
+        let timer = async move {
+            tokio::time::sleep(delay).await;
+            inner.abort(Reason { message: "signal timed out".to_string(), timed_out: true });
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle.spawn(timer),
+            Err(_) => blocking::runtime().spawn(timer)
+        };
+        signal
+    }
+
+    /// Creates a signal which is aborted as soon as any of `signals` is, with the same reason.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use js_lib::{AbortController, AbortSignal};
+    /// let controller = AbortController::new();
+    /// let signal = AbortSignal::any([controller.signal(), AbortSignal::timeout(5000)]);
+    /// controller.abort("cancelled");
+    /// assert_eq!(signal.reason().as_deref(), Some("cancelled"));
+    /// ```
+    pub fn any(signals: impl IntoIterator<Item = AbortSignal>) -> AbortSignal {
+        let signal = AbortSignal::default();
+        for source in signals {
+            if let Some(reason) = source.inner.reason() {
+                signal.inner.abort(reason);
+                break;
+            }
+            {
+                // A long lived source would otherwise keep an entry for every signal ever made from it.
+                let mut dependents = source.inner.dependents.lock().unwrap();
+                dependents.retain(|dependent| dependent.strong_count() > 0);
+                dependents.push(Arc::downgrade(&signal.inner));
+            }
+            // The source may have been aborted before this signal was registered.
+            if let Some(reason) = source.inner.reason() {
+                signal.inner.abort(reason);
+                break;
+            }
+        }
+        signal
+    }
+
+    /// Whether the signal has been aborted.
+    pub fn aborted(&self) -> bool {
+        self.inner.reason().is_some()
+    }
+
+    /// The reason the signal was aborted with, or `None` if it has not been aborted.
+    pub fn reason(&self) -> Option<String> {
+        self.inner.reason().map(|reason| reason.message)
+    }
+
+    /// Fails with `Error::AbortError`, or `Error::TimeoutError` for a timeout signal, if the
+    /// signal has been aborted.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - the signal has been aborted
+    pub fn throw_if_aborted(&self) -> Result<()> {
+        match self.error() {
+            Some(error) => Err(error),
+            None => Ok(())
+        }
+    }
+
+    /// The error requests using the signal fail with, or `None` if it has not been aborted.
+    fn error(&self) -> Option<Error> {
+        let reason = self.inner.reason()?;
+        if reason.timed_out {
+            return Some(Error::timeout_error(reason.message));
+        }
+        Some(Error::abort_error(reason.message))
+    }
+
+    /// Waits until the signal is aborted, returning the error requests using it fail with.
+    pub(crate) async fn aborted_error(&self) -> Error {
+        loop {
+            let notified = self.inner.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if let Some(error) = self.error() {
+                return error;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Runs a future to completion, unless the signal is aborted first.
+pub(crate) async fn abortable<F, T>(signal: Option<&AbortSignal>, future: F) -> Result<T>
+    where F: Future<Output = Result<T>> {
+    match signal {
+        Some(signal) => {
+            signal.throw_if_aborted()?;
+            tokio::select! {
+                biased;
+                error = signal.aborted_error() => Err(error),
+                result = future => result
+            }
+        },
+        None => future.await
+    }
+}
+
+/// Runs a future to completion, failing with `Error::TimeoutError` once `deadline` passes.
+pub(crate) async fn before<F, T>(deadline: Option<Instant>, future: F) -> Result<T>
+    where F: Future<Output = Result<T>> {
+    match deadline {
+        Some(deadline) => match tokio::time::timeout_at(deadline, future).await {
+            Ok(result) => result,
+            Err(elapsed) => Err(Error::from(elapsed))
+        },
+        None => future.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::TestServer;
+    use crate::{fetch_with, RequestInit};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn abort_in_flight_request() {
+        let server = TestServer::start_parts(|_| vec![(Duration::from_secs(60), Vec::new())]).await;
+        let controller = AbortController::new();
+        let url = server.url("/slow");
+        let signal = controller.signal();
+        let request = tokio::spawn(async move {
+            fetch_with(&url, RequestInit { signal: Some(signal), ..Default::default() }).await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        controller.abort("user cancelled");
+        match request.await.unwrap() {
+            Err(Error::AbortError { message, .. }) => assert_eq!(message, "user cancelled"),
+            other => panic!("expected an abort, got {:?}", other)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn timeout_aborts_body_reading() {
+        let head = b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nhello".to_vec();
+        let server = TestServer::start_parts(move |_| vec![
+            (Duration::ZERO, head.clone()),
+            (Duration::from_secs(60), Vec::new())
+        ]).await;
+        let controller = AbortController::new();
+        let signal = AbortSignal::any([controller.signal(), AbortSignal::timeout(100)]);
+        let response = fetch_with(&server.url("/"), RequestInit {
+            signal: Some(signal),
+            ..Default::default()
+        }).await.unwrap();
+        match response.text().await {
+            Err(Error::TimeoutError { message, .. }) => assert_eq!(message, "signal timed out"),
+            other => panic!("expected a timeout, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn any_keeps_the_first_reason() {
+        let first = AbortController::new();
+        let second = AbortController::new();
+        let signal = AbortSignal::any([first.signal(), second.signal()]);
+        assert!(!signal.aborted());
+        second.abort("second");
+        first.abort("first");
+        assert_eq!(signal.reason().as_deref(), Some("second"));
+        assert!(AbortSignal::any([AbortSignal::abort("already")]).aborted());
+        let source = AbortController::new();
+        for _ in 0..10 {
+            drop(AbortSignal::any([source.signal()]));
+        }
+        let _kept = AbortSignal::any([source.signal()]);
+        assert_eq!(source.signal.inner.dependents.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn timeouts_without_a_runtime_run_on_the_background_runtime() {
+        // Pending timers are tasks rather than sleeping threads, so many of them are cheap.
+        let pending = (0..1000).map(|_| AbortSignal::timeout(60_000)).collect::<Vec<_>>();
+        let signal = AbortSignal::timeout(10);
+        let started = std::time::Instant::now();
+        while !signal.aborted() && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(signal.reason().as_deref(), Some("signal timed out"));
+        assert!(pending.iter().all(|signal| !signal.aborted()));
+    }
+}
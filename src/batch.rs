@@ -0,0 +1,266 @@
+//! The `fetch_all` of many requests, with limits on how many run at once and how fast they start.
+
+use crate::client::{self, FetchClient};
+use crate::{RequestInit, Response, Result};
+use futures_util::stream::{FuturesUnordered, Stream, StreamExt};
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+
+/// The limits of the requests of `fetch_all` and `fetch_all_stream`.
+///
+/// A request counts against the concurrency limits from when it is sent until its response
+/// status and headers are received, and against the rate limit when it is sent. Requests start
+/// in the order they are given, as the limits allow.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{fetch_all, FetchAllOptions, RateLimit, RequestInit};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let urls = ["https://www.google.com/", "https://www.rust-lang.org/"];
+/// let responses = fetch_all(urls.map(|url| (url, RequestInit::default())), FetchAllOptions {
+///     concurrency: 8,
+///     per_host: Some(2),
+///     rate_limit: Some(RateLimit::per_second(10)),
+/// }).await;
+/// for response in responses {
+///     println!("{}", response?.status());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FetchAllOptions {
+    /// The most requests in flight at once, `16` by default.
+    pub concurrency: usize,
+    /// The most requests in flight to the same host at once, unlimited by default.
+    pub per_host: Option<usize>,
+    /// The rate requests are sent at, unlimited by default.
+    pub rate_limit: Option<RateLimit>
+}
+
+/// A token bucket limiting the rate requests are sent at.
+///
+/// The bucket holds up to `burst` tokens and is refilled with `requests` tokens per `interval`.
+/// Each request takes a token, waiting for one when the bucket is empty, so after a burst the
+/// requests are spread evenly over time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The tokens added per `interval`.
+    pub requests: u32,
+    /// The time `requests` tokens are added in.
+    pub interval: Duration,
+    /// The most tokens the bucket holds, which are the requests sent at once after a pause.
+    pub burst: u32
+}
+
+impl Default for FetchAllOptions {
+    fn default() -> Self {
+        FetchAllOptions { concurrency: 16, per_host: None, rate_limit: None }
+    }
+}
+
+impl RateLimit {
+    /// Allows `requests` per `interval`, in bursts of up to `requests`.
+    pub fn new(requests: u32, interval: Duration) -> RateLimit {
+        RateLimit { requests, interval, burst: requests }
+    }
+
+    /// Allows `requests` per second, in bursts of up to `requests`.
+    pub fn per_second(requests: u32) -> RateLimit {
+        RateLimit::new(requests, Duration::from_secs(1))
+    }
+
+    /// Sets the most requests sent at once after a pause.
+    pub fn burst(mut self, burst: u32) -> RateLimit {
+        self.burst = burst;
+        self
+    }
+}
+
+/// The shared state of a `RateLimit`.
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    state: Mutex<(f64, Instant)>
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> TokenBucket {
+        // A bucket which never refills or holds no token would never send a request.
+        let limit = RateLimit { requests: limit.requests.max(1), burst: limit.burst.max(1), ..limit };
+        TokenBucket { limit, state: Mutex::new((limit.burst as f64, Instant::now())) }
+    }
+
+    /// Takes a token, waiting until one is added when the bucket is empty.
+    async fn take(&self) {
+        let per_token = self.limit.interval.as_secs_f64() / self.limit.requests as f64;
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let (tokens, refilled) = &mut *state;
+                let now = Instant::now();
+                let added = now.duration_since(*refilled).as_secs_f64() / per_token;
+                *tokens = (*tokens + added).min(self.limit.burst as f64);
+                *refilled = now;
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - *tokens) * per_token)
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Fetches every request with `client`, or the global client when it is `None`, within the
+/// limits of `options`, streaming each response with the index of its request as it is received.
+pub(crate) fn fetch_all<I, U>(
+    client: Option<FetchClient>,
+    requests: I,
+    options: FetchAllOptions
+) -> impl Stream<Item = (usize, Result<Response>)> + Send + Unpin + 'static
+    where I: IntoIterator<Item = (U, RequestInit)>, U: Into<String> {
+    let concurrency = Arc::new(Semaphore::new(options.concurrency.max(1)));
+    let rate_limit = options.rate_limit.map(|limit| Arc::new(TokenBucket::new(limit)));
+    let mut hosts = HashMap::new();
+    let fetches = FuturesUnordered::new();
+    for (index, (url, init)) in requests.into_iter().enumerate() {
+        let url = url.into();
+        let url = client.as_ref().map_or(url.clone(), |client| client.resolve(&url));
+        let host = options.per_host.map(|per_host| {
+            let host = Url::parse(&url).ok().and_then(|url| url.host_str().map(str::to_string));
+            hosts.entry(host.unwrap_or_default())
+                .or_insert_with(|| Arc::new(Semaphore::new(per_host.max(1))))
+                .clone()
+        });
+        let (client, concurrency, rate_limit) = (client.clone(), concurrency.clone(), rate_limit.clone());
+        fetches.push(async move {
+            // The permit of the host is taken first, so a request waiting on its host does not
+            // keep a request to another host from being sent.
+            let _host = match &host {
+                Some(host) => Some(host.acquire().await.unwrap()),
+                None => None
+            };
+            let _permit = concurrency.acquire().await.unwrap();
+            if let Some(rate_limit) = &rate_limit {
+                rate_limit.take().await;
+            }
+            let result = match client::or_global(client.as_ref()) {
+                Ok(client) => client.fetch_with(&url, init).await,
+                Err(error) => Err(error)
+            };
+            (index, result)
+        });
+    }
+    fetches
+}
+
+/// Collects the results of `fetch_all` in the order of their requests.
+pub(crate) async fn in_order<S>(results: S) -> Vec<Result<Response>>
+    where S: Stream<Item = (usize, Result<Response>)> {
+    let mut results = results.collect::<Vec<_>>().await;
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, Request, ResponseInit, Transport};
+    use futures_util::future::BoxFuture;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A transport answering every request after a delay, counting the requests in flight.
+    #[derive(Debug, Default)]
+    struct Counting {
+        in_flight: Arc<Mutex<HashMap<String, usize>>>,
+        most: Arc<Mutex<HashMap<String, usize>>>,
+        sent: Arc<AtomicUsize>
+    }
+
+    impl Transport for Counting {
+        fn send(&self, request: Request) -> BoxFuture<'static, Result<Response>> {
+            let host = Url::parse(request.url()).unwrap().host_str().unwrap().to_string();
+            let (in_flight, most) = (self.in_flight.clone(), self.most.clone());
+            self.sent.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                for key in [host.clone(), "*".to_string()] {
+                    let count = {
+                        let mut in_flight = in_flight.lock().unwrap();
+                        let count = in_flight.entry(key.clone()).or_default();
+                        *count += 1;
+                        *count
+                    };
+                    let mut most = most.lock().unwrap();
+                    let most = most.entry(key).or_default();
+                    *most = (*most).max(count);
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                for key in [host.clone(), "*".to_string()] {
+                    *in_flight.lock().unwrap().get_mut(&key).unwrap() -= 1;
+                }
+                if request.url().ends_with("/missing") {
+                    return Err(Error::type_error("missing"));
+                }
+                Ok(Response::new(request.url().to_string(), ResponseInit::default()))
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn results_keep_the_order_within_the_limits() {
+        let transport = Counting::default();
+        let most = transport.most.clone();
+        let client = FetchClient::builder()
+            .base_url("https://a.example.com")
+            .transport(transport)
+            .build()
+            .unwrap();
+        let mut urls = (0..12).map(|index| format!("/{}", index)).collect::<Vec<_>>();
+        urls.extend((0..12).map(|index| format!("https://b.example.com/{}", index)));
+        urls[5] = "/missing".to_string();
+        let requests = urls.iter().map(|url| (url.as_str(), RequestInit::default()));
+        let options = FetchAllOptions { concurrency: 6, per_host: Some(4), rate_limit: None };
+        let results = client.fetch_all(requests, options).await;
+        assert_eq!(results.len(), 24);
+        assert!(matches!(results[5], Err(Error::TypeError { .. })));
+        for (result, url) in results.into_iter().zip(&urls).filter(|(_, url)| *url != "/missing") {
+            let text = result.unwrap().text().await.unwrap();
+            assert!(text.ends_with(url.trim_start_matches("https://b.example.com")), "{} for {}", text, url);
+        }
+        let most = most.lock().unwrap();
+        assert_eq!(most["*"], 6);
+        assert_eq!(most["a.example.com"], 4);
+        assert_eq!(most["b.example.com"], 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn the_rate_limit_spreads_the_requests() {
+        let transport = Counting::default();
+        let sent = transport.sent.clone();
+        let client = FetchClient::builder().transport(transport).build().unwrap();
+        let requests = (0..6).map(|index| (format!("https://example.com/{}", index), RequestInit::default()));
+        let options = FetchAllOptions {
+            rate_limit: Some(RateLimit::new(1, Duration::from_millis(50)).burst(2)),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let mut stream = client.fetch_all_stream(requests, options);
+        let mut indexes = Vec::new();
+        while let Some((index, result)) = stream.next().await {
+            assert!(result.is_ok());
+            indexes.push(index);
+        }
+        // Two requests are sent at once, then one every 50ms.
+        assert!(started.elapsed() >= Duration::from_millis(200), "{:?}", started.elapsed());
+        indexes.sort();
+        assert_eq!(indexes, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(sent.load(Ordering::SeqCst), 6);
+    }
+}
@@ -0,0 +1,235 @@
+//! Blocking versions of `fetch`, `fetch_with` and `Response`, for callers which are not async.
+//!
+//! Like the synchronous mode of javascript's `XMLHttpRequest`, each call blocks the current
+//! thread until it is done. Requests run on a background runtime owned by `js_lib`, so no tokio
+//! runtime is needed, and calling from inside one works too.
+//!
+//! **NOTE**: A blocking function can not be called from code running on that background runtime,
+//! like an interceptor or transport of a blocking request, which would wait on itself forever.
+//! Such a call fails with `Error::TypeError` instead.
+//!
+//! ```rust
+//! use js_lib::blocking;
+//! # fn example() -> Result<(), js_lib::Error> {
+//! let text = blocking::fetch("https://www.google.com/")?.text()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{Error, Headers, RequestInit, Result};
+use bytes::Bytes;
+use std::future::Future;
+use std::sync::OnceLock;
+
+pub use crate::from_json;
+
+/// A blocking http response, mirroring the javascript `Response`.
+///
+/// Reading the body blocks until it is read completely.
+#[derive(Debug)]
+pub struct Response {
+    inner: crate::Response
+}
+
+/// Fetches data from a url, blocking until the response is received.
+///
+/// **NOTE**: This function makes a http GET request, use `fetch_with` for any other request.
+///
+/// # Errors
+///
+/// This function fails if:
+///
+/// - any error of the async `js_lib::fetch` occurs
+pub fn fetch(url: &str) -> Result<Response> {
+    fetch_with(url, RequestInit::default())
+}
+
+/// Fetches data from a url with the options of `init`, blocking until the response is received.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{blocking, Method, RequestInit};
+/// # fn example() -> Result<(), js_lib::Error> {
+/// let response = blocking::fetch_with("https://www.google.com/", RequestInit {
+///     method: Method::POST,
+///     body: Some("hello".into()),
+///     ..Default::default()
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// This function fails if:
+///
+/// - any error of the async `js_lib::fetch_with` occurs
+pub fn fetch_with(url: &str, init: RequestInit) -> Result<Response> {
+    let url = url.to_string();
+    let inner = run(async move { crate::fetch_with(&url, init).await })?;
+    Ok(Response { inner })
+}
+
+impl Response {
+    /// The http status code, e.g. `200`.
+    pub fn status(&self) -> u16 {
+        self.inner.status()
+    }
+
+    /// The reason phrase of the status code, e.g. `"OK"`, or an empty string when it is unknown.
+    pub fn status_text(&self) -> &'static str {
+        self.inner.status_text()
+    }
+
+    /// Whether the status code is in the range `200` to `299`.
+    pub fn ok(&self) -> bool {
+        self.inner.ok()
+    }
+
+    /// The response headers.
+    pub fn headers(&self) -> &Headers {
+        self.inner.headers()
+    }
+
+    /// The final url of the response, after any redirects were followed.
+    pub fn url(&self) -> &str {
+        self.inner.url()
+    }
+
+    /// Whether the response is the result of following one or more redirects.
+    pub fn redirected(&self) -> bool {
+        self.inner.redirected()
+    }
+
+    /// Reads the body as utf-8 text.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - there is an error reading the body
+    /// - the request's signal is aborted
+    pub fn text(self) -> Result<String> {
+        run(self.inner.text())
+    }
+
+    /// Reads the body and deserializes it as json into type T.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - there is an error reading the body
+    /// - the request's signal is aborted
+    /// - there is an error parsing the body as json
+    pub fn json<T>(self) -> Result<T>
+        where T: serde::de::DeserializeOwned + Send + 'static {
+        run(self.inner.json::<T>())
+    }
+
+    /// Reads the body as bytes.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - there is an error reading the body
+    /// - the request's signal is aborted
+    pub fn bytes(self) -> Result<Bytes> {
+        run(self.inner.bytes())
+    }
+
+    /// Reads the body into an owned buffer of bytes.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - there is an error reading the body
+    /// - the request's signal is aborted
+    pub fn array_buffer(self) -> Result<Vec<u8>> {
+        run(self.inner.array_buffer())
+    }
+
+    /// Converts the response into an async `Response`.
+    pub fn into_async(self) -> crate::Response {
+        self.inner
+    }
+}
+
+/// Runs a fallible `future` with `block_on`, unless the current thread is a worker of the
+/// background runtime, whose only worker would then wait for itself.
+fn run<F, T>(future: F) -> Result<T>
+    where F: Future<Output = Result<T>> + Send + 'static, T: Send + 'static {
+    let current = tokio::runtime::Handle::try_current().map(|handle| handle.id());
+    if current.ok() == Some(runtime().handle().id()) {
+        let message = "a blocking function can not be called from the runtime of the blocking functions";
+        return Err(Error::type_error(message));
+    }
+    block_on(future)
+}
+
+/// Runs `future` on the background runtime, blocking the current thread until it completes.
+///
+/// Unlike `Runtime::block_on`, this does not panic when the current thread is already running
+/// a runtime, because the future is spawned rather than driven by the current thread.
+fn block_on<F>(future: F) -> F::Output
+    where F: Future + Send + 'static, F::Output: Send + 'static {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    runtime().spawn(async move {
+        let _ = sender.send(future.await);
+    });
+    match receiver.recv() {
+        Ok(output) => output,
+        Err(_) => panic!("a blocking request panicked")
+    }
+}
+
+/// The background runtime of the blocking functions, created on first use.
+pub(crate) fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("js_lib-blocking")
+            .enable_all()
+            .build()
+            .expect("the blocking runtime could not be created")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{response, TestServer};
+    use crate::Method;
+
+    #[test]
+    fn fetch_without_a_runtime() {
+        let server = block_on(TestServer::start(|request| response(200, &[], request.method.as_bytes())));
+        let response = fetch_with(&server.url("/"), RequestInit {
+            method: Method::PUT,
+            ..Default::default()
+        }).unwrap();
+        assert!(response.ok());
+        assert_eq!(response.text().unwrap(), "PUT");
+        let words = fetch(&server.url("/")).unwrap().json::<String>();
+        match words {
+            Err(error @ Error::SyntaxError { .. }) => {
+                assert_eq!((error.status(), error.url()), (Some(200), Some(server.url("/").as_str())));
+            },
+            other => panic!("expected a syntax error, got {:?}", other)
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_inside_a_runtime() {
+        let server = block_on(TestServer::start(|_| response(200, &[], br#"["a","b"]"#)));
+        let words = fetch(&server.url("/")).unwrap().json::<Vec<String>>().unwrap();
+        assert_eq!(words, ["a", "b"]);
+        let url = server.url("/");
+        let nested = block_on(async move { fetch(&url).map(|_| ()) });
+        assert!(matches!(nested, Err(Error::TypeError { .. })));
+    }
+}
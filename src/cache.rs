@@ -0,0 +1,519 @@
+//! The `HttpCache` which serves repeated `fetch` calls without going back to the network.
+
+use crate::date::{parse_date, unix_time};
+use crate::{Headers, ReadableStream, Response};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The default most bytes of a single stored response, 8 MiB.
+const DEFAULT_MAX_ENTRY_SIZE: u64 = 8 << 20;
+
+/// The default most bytes of every stored response, 64 MiB.
+const DEFAULT_MAX_SIZE: u64 = 64 << 20;
+
+/// How a request uses the http cache, mirroring the javascript `cache` option.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RequestCache {
+    /// A fresh cached response is used, a stale one is revalidated with the server.
+    #[default]
+    Default,
+    /// The cache is neither read nor updated.
+    NoStore,
+    /// The cache is not read, but is updated with the response.
+    Reload,
+    /// A cached response is always revalidated with the server before it is used.
+    NoCache,
+    /// A cached response is used however stale it is, the network is only used on a miss.
+    ForceCache,
+    /// A cached response is used however stale it is, and a miss fails the request.
+    OnlyIfCached
+}
+
+/// A private http cache following the freshness and validation rules of RFC 9111, kept in
+/// memory or in a directory on disk.
+///
+/// Only responses to GET requests are cached, and neither partial responses nor responses to
+/// requests with a `range` header are. Clones of a cache share the same entries.
+///
+/// A response larger than `HttpCache::max_entry_size` is not stored, and once the stored responses
+/// take more than `HttpCache::max_size` the oldest ones are evicted.
+///
+/// **NOTE**: The disk cache reads one file per lookup and writes one file per stored response,
+/// listing the directory to evict the oldest files once it grows over its size limit.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{fetch_with, HttpCache, RequestInit};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let cache = HttpCache::memory();
+/// for _ in 0..3 {
+///     let response = fetch_with("https://www.google.com/", RequestInit {
+///         http_cache: Some(cache.clone()),
+///         ..Default::default()
+///     }).await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HttpCache {
+    storage: Arc<Storage>,
+    max_entry_size: u64,
+    max_size: u64
+}
+
+#[derive(Debug)]
+enum Storage {
+    Memory(Mutex<Memory>),
+    Disk(PathBuf)
+}
+
+/// The entries of a memory cache, each with the order it was stored in, and their total size.
+#[derive(Debug, Default)]
+struct Memory {
+    entries: HashMap<String, (u64, Entry)>,
+    stored: u64,
+    size: u64
+}
+
+/// A stored response, with what is needed to compute its age and match its `vary` header.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    url: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    vary: Vec<(String, Option<String>)>,
+    request_time: i64,
+    response_time: i64,
+    #[serde(skip)]
+    body: Bytes
+}
+
+impl HttpCache {
+    /// Creates a cache kept in memory.
+    pub fn memory() -> HttpCache {
+        HttpCache::new(Storage::Memory(Mutex::default()))
+    }
+
+    /// Creates a cache kept in the directory `path`, which is created when the first response
+    /// is stored.
+    pub fn disk(path: impl Into<PathBuf>) -> HttpCache {
+        HttpCache::new(Storage::Disk(path.into()))
+    }
+
+    fn new(storage: Storage) -> HttpCache {
+        HttpCache {
+            storage: Arc::new(storage),
+            max_entry_size: DEFAULT_MAX_ENTRY_SIZE,
+            max_size: DEFAULT_MAX_SIZE
+        }
+    }
+
+    /// Sets the most bytes of a single stored response, 8 MiB by default. A larger response is
+    /// still received, but stops being buffered for the cache once it grows over the limit.
+    pub fn max_entry_size(mut self, max_entry_size: u64) -> HttpCache {
+        self.max_entry_size = max_entry_size;
+        self
+    }
+
+    /// Sets the most bytes of every stored response, 64 MiB by default, over which the oldest
+    /// responses are evicted.
+    pub fn max_size(mut self, max_size: u64) -> HttpCache {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Removes every cached response.
+    pub async fn clear(&self) {
+        match &*self.storage {
+            Storage::Memory(memory) => *memory.lock().unwrap() = Memory::default(),
+            Storage::Disk(path) => {
+                let _ = tokio::fs::remove_dir_all(path).await;
+            }
+        }
+    }
+
+    /// The stored response for `url`, if its `vary` header matches the request `headers`.
+    pub(crate) async fn lookup(&self, url: &str, headers: &Headers) -> Option<Entry> {
+        let entry = match &*self.storage {
+            Storage::Memory(memory) => memory.lock().unwrap().entries.get(url)?.1.clone(),
+            Storage::Disk(path) => {
+                // The file holds the entry as a line of json, followed by the body.
+                let file = Bytes::from(tokio::fs::read(entry_path(path, url)).await.ok()?);
+                let newline = file.iter().position(|byte| *byte == b'\n')?;
+                let mut entry = serde_json::from_slice::<Entry>(&file[..newline]).ok()?;
+                entry.body = file.slice(newline + 1..);
+                entry
+            }
+        };
+        let vary_matches = entry.vary.iter().all(|(name, value)| headers.get(name) == *value);
+        Some(entry).filter(|entry| entry.url == url && vary_matches)
+    }
+
+    /// Removes the stored response for `url`, after an unsafe request changed it.
+    pub(crate) async fn invalidate(&self, url: &str) {
+        match &*self.storage {
+            Storage::Memory(memory) => memory.lock().unwrap().remove(url),
+            Storage::Disk(path) => {
+                let _ = tokio::fs::remove_file(entry_path(path, url)).await;
+            }
+        }
+    }
+
+    /// Stores `response` once its body has been read completely, if it is storable.
+    pub(crate) fn store(
+        &self,
+        url: &str,
+        headers: &Headers,
+        request_time: i64,
+        mut response: Response
+    ) -> Response {
+        let directives = cache_control(response.headers());
+        let vary = response.headers().get("vary").unwrap_or_default();
+        let explicit = directives.contains_key("max-age") || response.headers().has("expires");
+        let validated = response.headers().has("etag") || response.headers().has("last-modified");
+        let content_length = response.headers().get("content-length").and_then(|length| length.parse().ok());
+        // A partial response is only part of the body of its url, so it is not stored under it.
+        let storable = !directives.contains_key("no-store")
+            && vary.trim() != "*"
+            && response.status() != 206
+            && !headers.has("range")
+            && content_length.is_none_or(|length: u64| length <= self.max_entry_size)
+            && (explicit || (validated && HEURISTIC_STATUSES.contains(&response.status())));
+        if !storable {
+            return response;
+        }
+        let Some(body) = response.body() else {
+            return response;
+        };
+        let vary = vary.split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let value = headers.get(&name);
+                (name, value)
+            })
+            .collect();
+        let entry = Entry {
+            url: url.to_string(),
+            status: response.status(),
+            headers: response.headers().raw().to_vec(),
+            vary,
+            request_time,
+            response_time: unix_time(),
+            body: Bytes::new()
+        };
+        let cache = self.clone();
+        let chunks = futures_util::stream::unfold(
+            (body, Vec::new(), Some(entry)),
+            move |(mut body, mut buffer, mut entry)| {
+                let cache = cache.clone();
+                async move {
+                    match body.next().await {
+                        Some(Ok(chunk)) => {
+                            if entry.is_some() && (buffer.len() + chunk.len()) as u64 > cache.max_entry_size {
+                                entry = None;
+                                buffer = Vec::new();
+                            } else if entry.is_some() {
+                                buffer.extend_from_slice(&chunk);
+                            }
+                            Some((Ok(chunk), (body, buffer, entry)))
+                        },
+                        Some(Err(error)) => Some((Err(error), (body, Vec::new(), None))),
+                        None => {
+                            if let Some(mut entry) = entry.take() {
+                                entry.body = Bytes::from(buffer);
+                                cache.insert(entry).await;
+                            }
+                            None
+                        }
+                    }
+                }
+            }
+        );
+        response.with_body(ReadableStream::new(chunks))
+    }
+
+    /// Updates a stored response with the headers of a `304 Not Modified` response.
+    pub(crate) async fn refresh(&self, mut entry: Entry, not_modified: &Headers, request_time: i64) -> Entry {
+        let updated = |name: &str| {
+            let framing = matches!(name, "content-length" | "content-encoding" | "transfer-encoding");
+            !framing && not_modified.has(name)
+        };
+        entry.headers.retain(|(name, _)| !updated(name));
+        entry.headers.extend(not_modified.raw().iter().filter(|(name, _)| updated(name)).cloned());
+        entry.request_time = request_time;
+        entry.response_time = unix_time();
+        self.insert(entry.clone()).await;
+        entry
+    }
+
+    async fn insert(&self, entry: Entry) {
+        match &*self.storage {
+            Storage::Memory(memory) => memory.lock().unwrap().insert(entry, self.max_size),
+            Storage::Disk(path) => {
+                // A failure to write only means the response is not cached.
+                if write_entry(path, &entry).await.is_ok() {
+                    let _ = evict(path, self.max_size).await;
+                }
+            }
+        }
+    }
+}
+
+impl Memory {
+    /// Stores `entry`, then evicts the oldest entries until they take at most `max_size` bytes.
+    fn insert(&mut self, entry: Entry, max_size: u64) {
+        self.remove(&entry.url);
+        self.stored += 1;
+        self.size += entry.size();
+        self.entries.insert(entry.url.clone(), (self.stored, entry));
+        while self.size > max_size {
+            let oldest = self.entries.iter().min_by_key(|(_, (stored, _))| *stored);
+            match oldest.map(|(url, _)| url.clone()) {
+                Some(url) => self.remove(&url),
+                None => break
+            }
+        }
+    }
+
+    fn remove(&mut self, url: &str) {
+        if let Some((_, entry)) = self.entries.remove(url) {
+            self.size -= entry.size();
+        }
+    }
+}
+
+/// The statuses whose responses may be stored without explicit freshness, as in RFC 9110.
+const HEURISTIC_STATUSES: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+impl Entry {
+    /// Whether the response is fresh, following RFC 9111 section 4.2.
+    pub(crate) fn is_fresh(&self) -> bool {
+        let headers = self.headers();
+        let directives = cache_control(&headers);
+        if directives.contains_key("no-cache") {
+            return false;
+        }
+        let date = headers.get("date").and_then(|date| parse_date(&date)).unwrap_or(self.response_time);
+        let lifetime = match directives.get("max-age").and_then(|age| age.as_deref()) {
+            Some(max_age) => max_age.parse::<i64>().unwrap_or(0),
+            None => match headers.get("expires") {
+                // An invalid date, like "0", means the response has already expired.
+                Some(expires) => parse_date(&expires).map_or(0, |expires| expires - date),
+                None => match headers.get("last-modified").and_then(|modified| parse_date(&modified)) {
+                    Some(modified) if HEURISTIC_STATUSES.contains(&self.status) => (date - modified) / 10,
+                    _ => 0
+                }
+            }
+        };
+        let age = headers.get("age").and_then(|age| age.trim().parse::<i64>().ok()).unwrap_or(0);
+        let apparent_age = (self.response_time - date).max(0);
+        let corrected_age = age + (self.response_time - self.request_time);
+        let current_age = apparent_age.max(corrected_age) + (unix_time() - self.response_time);
+        lifetime > current_age
+    }
+
+    pub(crate) fn etag(&self) -> Option<String> {
+        self.headers().get("etag")
+    }
+
+    pub(crate) fn last_modified(&self) -> Option<String> {
+        self.headers().get("last-modified")
+    }
+
+    pub(crate) fn into_response(self) -> Response {
+        let headers = self.headers();
+        let body = futures_util::stream::iter([Ok(self.body)]);
+        Response::from_parts(self.status, headers, self.url, ReadableStream::new(body))
+    }
+
+    fn headers(&self) -> Headers {
+        Headers::from_raw(self.headers.clone())
+    }
+
+    /// The bytes the entry takes in a memory cache, counting its headers and body.
+    fn size(&self) -> u64 {
+        let headers = self.headers.iter().map(|(name, value)| name.len() + value.len()).sum::<usize>();
+        (self.url.len() + headers + self.body.len()) as u64
+    }
+}
+
+/// The directives of the `cache-control` header, with their unquoted values.
+fn cache_control(headers: &Headers) -> HashMap<String, Option<String>> {
+    let value = headers.get("cache-control").unwrap_or_default();
+    value.split(',')
+        .filter(|directive| !directive.trim().is_empty())
+        .map(|directive| {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim().trim_matches('"').to_string())),
+                None => (directive, None)
+            };
+            (name.trim().to_ascii_lowercase(), value)
+        })
+        .collect()
+}
+
+/// The file of the entry of `url` in the directory `path`, named from its 64 bit FNV-1a hash.
+fn entry_path(path: &Path, url: &str) -> PathBuf {
+    let hash = url.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    path.join(format!("{:016x}.entry", hash))
+}
+
+/// Writes `entry` to its file in `path`, through a temporary file renamed over it so a lookup
+/// never reads a partly written entry.
+async fn write_entry(path: &Path, entry: &Entry) -> io::Result<()> {
+    static TEMPORARY_FILES: AtomicU64 = AtomicU64::new(0);
+    let mut contents = serde_json::to_vec(entry)?;
+    contents.push(b'\n');
+    contents.extend_from_slice(&entry.body);
+    tokio::fs::create_dir_all(path).await?;
+    let file = entry_path(path, &entry.url);
+    let count = TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed);
+    let temporary = file.with_extension(format!("{}-{}.tmp", std::process::id(), count));
+    let written = match tokio::fs::write(&temporary, contents).await {
+        Ok(()) => tokio::fs::rename(&temporary, &file).await,
+        Err(error) => Err(error)
+    };
+    if written.is_err() {
+        let _ = tokio::fs::remove_file(&temporary).await;
+    }
+    written
+}
+
+/// Removes the least recently written entries of `path` until they take at most `max_size` bytes.
+async fn evict(path: &Path, max_size: u64) -> io::Result<()> {
+    let mut files = Vec::new();
+    let mut directory = tokio::fs::read_dir(path).await?;
+    while let Some(file) = directory.next_entry().await? {
+        if file.path().extension().is_some_and(|extension| extension == "entry") {
+            let metadata = file.metadata().await?;
+            files.push((metadata.modified()?, metadata.len(), file.path()));
+        }
+    }
+    let mut size = files.iter().map(|(_, len, _)| len).sum::<u64>();
+    files.sort();
+    for (_, len, file) in files {
+        if size <= max_size {
+            break;
+        }
+        if tokio::fs::remove_file(&file).await.is_ok() {
+            size -= len;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{response, TestServer};
+    use crate::{fetch_with, Method, RequestInit, ResponseInit};
+
+    async fn cached_fetch(url: &str, cache: &HttpCache, mode: RequestCache) -> crate::Result<String> {
+        let response = fetch_with(url, RequestInit {
+            cache: mode,
+            http_cache: Some(cache.clone()),
+            ..Default::default()
+        }).await?;
+        response.text().await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fresh_responses_are_served_from_memory() {
+        let server = TestServer::start(|request| match request.target.as_str() {
+            "/fresh" => response(200, &[("cache-control", "max-age=60")], b"fresh"),
+            _ => response(200, &[("cache-control", "no-store")], b"never")
+        }).await;
+        let cache = HttpCache::memory();
+        for _ in 0..3 {
+            let text = cached_fetch(&server.url("/fresh"), &cache, RequestCache::Default).await;
+            assert_eq!(text.unwrap(), "fresh");
+            cached_fetch(&server.url("/never"), &cache, RequestCache::Default).await.unwrap();
+        }
+        assert_eq!(server.received().len(), 4);
+        cached_fetch(&server.url("/fresh"), &cache, RequestCache::Reload).await.unwrap();
+        cached_fetch(&server.url("/fresh"), &cache, RequestCache::NoStore).await.unwrap();
+        assert_eq!(server.received().len(), 6);
+        assert!(cached_fetch(&server.url("/never"), &cache, RequestCache::OnlyIfCached).await.is_err());
+        fetch_with(&server.url("/fresh"), RequestInit {
+            method: Method::POST,
+            http_cache: Some(cache.clone()),
+            ..Default::default()
+        }).await.unwrap();
+        assert!(cached_fetch(&server.url("/fresh"), &cache, RequestCache::OnlyIfCached).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stale_responses_are_revalidated_on_disk() {
+        let server = TestServer::start(|request| match request.header("if-none-match") {
+            Some("\"v1\"") => response(304, &[("x-revalidated", "yes")], b""),
+            _ => response(200, &[("etag", "\"v1\""), ("cache-control", "no-cache")], b"etag body")
+        }).await;
+        let path = std::env::temp_dir().join(format!("js_lib_cache_{}", std::process::id()));
+        let cache = HttpCache::disk(&path);
+        assert_eq!(cached_fetch(&server.url("/"), &cache, RequestCache::Default).await.unwrap(), "etag body");
+        assert_eq!(cached_fetch(&server.url("/"), &cache, RequestCache::Default).await.unwrap(), "etag body");
+        assert_eq!(cached_fetch(&server.url("/"), &cache, RequestCache::ForceCache).await.unwrap(), "etag body");
+        let received = server.received();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[1].header("if-none-match"), Some("\"v1\""));
+        let entry = cache.lookup(&server.url("/"), &Headers::new()).await.unwrap();
+        assert_eq!(entry.headers().get("x-revalidated").as_deref(), Some("yes"));
+        cache.clear().await;
+        assert!(!path.exists());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn large_and_partial_responses_are_not_stored() {
+        let cache = HttpCache::memory().max_entry_size(1000).max_size(2500);
+        let store = |url: &str, status: u16, request: Headers, chunks: usize| {
+            let chunks = (0..chunks).map(|_| Ok(Bytes::from(vec![b'a'; 300])));
+            let headers = Headers::from([("cache-control", "max-age=60")]);
+            let body = ReadableStream::new(futures_util::stream::iter(chunks.collect::<Vec<_>>()));
+            let response = Response::new(body, ResponseInit { status, headers });
+            cache.store(url, &request, unix_time(), response).bytes()
+        };
+        assert_eq!(store("/large", 200, Headers::new(), 4).await.unwrap().len(), 1200);
+        store("/partial", 206, Headers::new(), 1).await.unwrap();
+        store("/range", 200, Headers::from([("range", "bytes=0-299")]), 1).await.unwrap();
+        for url in ["/large", "/partial", "/range"] {
+            assert!(cache.lookup(url, &Headers::new()).await.is_none(), "{}", url);
+        }
+        for url in ["/0", "/1", "/2"] {
+            store(url, 200, Headers::new(), 3).await.unwrap();
+        }
+        assert!(cache.lookup("/0", &Headers::new()).await.is_none());
+        assert_eq!(cache.lookup("/2", &Headers::new()).await.unwrap().body.len(), 900);
+        assert!(cache.lookup("/1", &Headers::new()).await.is_some());
+    }
+
+    #[test]
+    fn freshness_lifetimes() {
+        let now = unix_time();
+        let entry = |headers: &[(&str, &str)]| Entry {
+            url: String::new(),
+            status: 200,
+            headers: headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            vary: Vec::new(),
+            request_time: now,
+            response_time: now,
+            body: Bytes::new()
+        };
+        assert!(entry(&[("cache-control", "max-age=60")]).is_fresh());
+        assert!(!entry(&[("cache-control", "max-age=60"), ("age", "120")]).is_fresh());
+        assert!(!entry(&[("expires", "0")]).is_fresh());
+        let date = ("date", "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert!(entry(&[date, ("expires", "Sun, 06 Nov 2094 08:49:37 GMT")]).is_fresh());
+        assert!(entry(&[("last-modified", "Sun, 06 Nov 1994 08:49:37 GMT")]).is_fresh());
+    }
+}
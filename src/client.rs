@@ -0,0 +1,508 @@
+//! The `FetchClient` which shares a connection pool and default options between requests.
+
+use crate::interceptor::Interceptors;
+use crate::transport::{self, HttpTransport, Transport};
+use crate::scheme::LocalTransport;
+use crate::{abort, batch, fetch, retry};
+use crate::{CookieJar, Error, Headers, HttpCache, Interceptor, RequestInit, Response, Result};
+use crate::{EventSource, FetchAllOptions, Proxy, RetryPolicy, TlsOptions, ValidateStatus};
+use crate::{WebSocket, WebSocketInit};
+use futures_util::Stream;
+use reqwest::Url;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A http client with its own connection pool and default options, like the client of
+/// javascript's `axios.create`.
+///
+/// Connections are kept alive and reused by every request of the client and its clones, saving
+/// a connection and TLS handshake per request. The free `fetch` and `fetch_with` functions use a
+/// global client created on their first call.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{FetchClient, Headers};
+/// use std::time::Duration;
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let client = FetchClient::builder()
+///     .base_url("https://www.google.com/")
+///     .default_headers(Headers::from([("accept", "text/html")]))
+///     .timeout(Duration::from_secs(10))
+///     .user_agent("js_lib")
+///     .build()?;
+/// let text = client.fetch("search?q=rust").await?.text().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FetchClient {
+    transport: Arc<dyn Transport>,
+    network: Option<NetworkOptions>,
+    tls_transports: TlsTransports,
+    interceptors: Interceptors,
+    base_url: Option<Url>,
+    headers: Headers,
+    timeout: Option<Duration>,
+    cookie_jar: Option<CookieJar>,
+    http_cache: Option<HttpCache>,
+    retry: Option<RetryPolicy>,
+    validate_status: Option<ValidateStatus>
+}
+
+/// Builds a `FetchClient`, created with `FetchClient::builder()`.
+#[derive(Debug, Default)]
+pub struct FetchClientBuilder {
+    base_url: Option<String>,
+    headers: Headers,
+    timeout: Option<Duration>,
+    network: NetworkOptions,
+    transport: Option<Arc<dyn Transport>>,
+    interceptors: Interceptors,
+    cookie_jar: Option<CookieJar>,
+    http_cache: Option<HttpCache>,
+    retry: Option<RetryPolicy>,
+    validate_status: Option<ValidateStatus>
+}
+
+/// The most network transports kept for requests with their own TLS options.
+const MAX_TLS_TRANSPORTS: usize = 8;
+
+/// The network transports of requests with their own TLS options, each with its options, from
+/// the least to the most recently used.
+type TlsTransports = Arc<Mutex<Vec<(TlsOptions, Arc<dyn Transport>)>>>;
+
+/// The options the network transport of a client is built with.
+#[derive(Debug, Clone, Default)]
+struct NetworkOptions {
+    user_agent: Option<String>,
+    proxies: Vec<Proxy>,
+    env_proxy: Option<bool>,
+    tls: Option<TlsOptions>,
+    allow_file_urls: bool
+}
+
+impl FetchClient {
+    /// Creates a client without any default options.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the TLS backend can not be initialized, use
+    /// `FetchClient::builder().build()` to handle the error instead.
+    pub fn new() -> FetchClient {
+        FetchClient::builder().build().expect("the http client could not be created")
+    }
+
+    /// Creates a builder to configure a client.
+    pub fn builder() -> FetchClientBuilder {
+        FetchClientBuilder::default()
+    }
+
+    /// Fetches data from a url, which is resolved against the base url of the client.
+    ///
+    /// **NOTE**: This function makes a http GET request, use `fetch_with` for any other request.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - any error of `FetchClient::fetch_with` occurs
+    pub async fn fetch(&self, url: &str) -> Result<Response> {
+        self.fetch_with(url, RequestInit::default()).await
+    }
+
+    /// Fetches data from a url, which is resolved against the base url of the client, with the
+    /// options of `init`.
+    ///
+    /// The default headers of the client are sent unless `init.headers` sets the same header, and
+    /// its cookie jar, http cache, retry policy, status validation and TLS options are used unless
+    /// `init` sets its own.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - any error of the free `fetch_with` function occurs
+    /// - a `file:` url is fetched while `FetchClientBuilder::allow_file_urls` is not set, with a
+    ///   method other than GET or HEAD, or its file can not be read
+    /// - the timeout of the client elapses before the response body is read
+    /// - `init.tls` is invalid
+    pub async fn fetch_with(&self, url: &str, init: RequestInit) -> Result<Response> {
+        self.fetch_timed(url, init, true).await
+    }
+
+    /// Fetches like `FetchClient::fetch_with`, with the timeout of the client covering the
+    /// response body only when `time_body` is set, as an event stream is meant to stay open.
+    pub(crate) async fn fetch_timed(
+        &self,
+        url: &str,
+        mut init: RequestInit,
+        time_body: bool
+    ) -> Result<Response> {
+        let url = self.resolve(url);
+        for (name, value) in self.headers.raw() {
+            if !init.headers.has(name) {
+                init.headers.append(name, value)?;
+            }
+        }
+        init.cookie_jar = init.cookie_jar.or_else(|| self.cookie_jar.clone());
+        init.http_cache = init.http_cache.or_else(|| self.http_cache.clone());
+        init.retry = init.retry.or_else(|| self.retry.clone());
+        init.validate_status = init.validate_status.or_else(|| self.validate_status.clone());
+        // The timeout is a deadline for the whole request, which the body keeps once it is returned.
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let signal = init.signal.clone();
+        let method = init.method.clone();
+        let validate_status = init.validate_status.clone();
+        let transport = match (transport::scoped(), &init.tls, &self.network) {
+            (Some(transport), _, _) => transport,
+            (None, Some(tls), Some(network)) => match self.tls_transport(network, tls) {
+                Ok(transport) => transport,
+                Err(error) => return Err(error.with_request(&method, &url))
+            },
+            _ => self.transport.clone()
+        };
+        let transport = self.interceptors.wrap(transport);
+        let fetch = async {
+            // The status is validated after retrying, so a retried status is retried before it fails.
+            let response = retry::retry(init, |init| fetch::fetch(&*transport, &url, init)).await?;
+            match &validate_status {
+                Some(validate_status) => validate_status.check(response).await,
+                None => Ok(response)
+            }
+        };
+        match abort::before(deadline, abort::abortable(signal.as_ref(), fetch)).await {
+            Ok(response) => Ok(response.with_deadline(deadline.filter(|_| time_body))),
+            Err(error) => Err(error.with_request(&method, &url))
+        }
+    }
+
+    /// Fetches many requests, whose urls are resolved against the base url of the client, within
+    /// the concurrency and rate limits of `options`, returning their results in the order of the
+    /// requests.
+    ///
+    /// Each request is sent as by `FetchClient::fetch_with`, so one failing does not stop the
+    /// others.
+    pub async fn fetch_all<I, U>(&self, requests: I, options: FetchAllOptions) -> Vec<Result<Response>>
+        where I: IntoIterator<Item = (U, RequestInit)>, U: Into<String> {
+        batch::in_order(batch::fetch_all(Some(self.clone()), requests, options)).await
+    }
+
+    /// Fetches many requests like `FetchClient::fetch_all`, streaming each result with the index of
+    /// its request as soon as it is received.
+    pub fn fetch_all_stream<I, U>(
+        &self,
+        requests: I,
+        options: FetchAllOptions
+    ) -> impl Stream<Item = (usize, Result<Response>)> + Send + Unpin + 'static
+        where I: IntoIterator<Item = (U, RequestInit)>, U: Into<String> {
+        batch::fetch_all(Some(self.clone()), requests, options)
+    }
+
+    /// Opens an `EventSource` to a url, which is resolved against the base url of the client,
+    /// sending the options of `init` with each connection.
+    ///
+    /// The events are received with the defaults of the client, like its headers and TLS options.
+    /// The timeout of the client covers each connection until its response headers are received,
+    /// not the event stream which follows.
+    pub fn event_source(&self, url: &str, init: RequestInit) -> EventSource {
+        EventSource::connect(Some(self.clone()), url, init)
+    }
+
+    /// Opens a `WebSocket` to a url, which is resolved against the base url of the client, with
+    /// the options of `init`.
+    ///
+    /// The default headers and TLS options of the client are used unless `init` sets its own. The
+    /// connection does not go through the transport, interceptors or proxies of the client.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - any error of `WebSocket::with_init` occurs
+    pub fn web_socket(&self, url: &str, mut init: WebSocketInit) -> Result<WebSocket> {
+        for (name, value) in self.headers.raw() {
+            if !init.headers.has(name) {
+                init.headers.append(name, value)?;
+            }
+        }
+        if init.tls.is_none() {
+            init.tls = self.network.as_ref().and_then(|network| network.tls.clone());
+        }
+        WebSocket::with_init(&self.resolve(url), init)
+    }
+
+    /// The network transport of requests with their own TLS options, reused while it is among
+    /// the `MAX_TLS_TRANSPORTS` most recently used, so varying options do not pile up pools.
+    fn tls_transport(&self, network: &NetworkOptions, tls: &TlsOptions) -> Result<Arc<dyn Transport>> {
+        let mut transports = self.tls_transports.lock().unwrap();
+        if let Some(index) = transports.iter().position(|(options, _)| options == tls) {
+            let entry = transports.remove(index);
+            let transport = entry.1.clone();
+            transports.push(entry);
+            return Ok(transport);
+        }
+        let transport = network.transport(Some(tls))?;
+        if transports.len() >= MAX_TLS_TRANSPORTS {
+            transports.remove(0);
+        }
+        transports.push((tls.clone(), transport.clone()));
+        Ok(transport)
+    }
+
+    /// Joins a relative `url` to the base url, with a single `/` between them.
+    pub(crate) fn resolve(&self, url: &str) -> String {
+        match &self.base_url {
+            Some(base_url) if Url::parse(url).is_err() => {
+                format!("{}/{}", base_url.as_str().trim_end_matches('/'), url.trim_start_matches('/'))
+            },
+            _ => url.to_string()
+        }
+    }
+}
+
+impl Default for FetchClient {
+    fn default() -> Self {
+        FetchClient::new()
+    }
+}
+
+impl FetchClientBuilder {
+    /// Sets the url relative request urls are joined to, e.g. `"https://api.example.com/v1"`.
+    pub fn base_url(mut self, base_url: &str) -> FetchClientBuilder {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+
+    /// Sets the headers sent with every request which does not set them itself.
+    pub fn default_headers(mut self, headers: Headers) -> FetchClientBuilder {
+        self.headers = headers;
+        self
+    }
+
+    /// Sets how long a request may take, including reading its response body, before it fails
+    /// with `Error::TimeoutError`.
+    pub fn timeout(mut self, timeout: Duration) -> FetchClientBuilder {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the `user-agent` header sent with every request.
+    pub fn user_agent(mut self, user_agent: &str) -> FetchClientBuilder {
+        self.network.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    /// Adds a proxy requests are sent through, when the proxies added before it do not apply.
+    ///
+    /// Once a proxy is added, the proxies of the environment are no longer used.
+    pub fn proxy(mut self, proxy: Proxy) -> FetchClientBuilder {
+        self.network.proxies.push(proxy);
+        self
+    }
+
+    /// Sets whether requests are sent through the proxies of the environment, as set by the
+    /// `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY` variables, when no proxy is added.
+    /// `true` by default.
+    pub fn env_proxy(mut self, env_proxy: bool) -> FetchClientBuilder {
+        self.network.env_proxy = Some(env_proxy);
+        self
+    }
+
+    /// Sets the TLS options of requests which do not set their own.
+    pub fn tls(mut self, tls: TlsOptions) -> FetchClientBuilder {
+        self.network.tls = Some(tls);
+        self
+    }
+
+    /// Allows fetching `file:` urls, which read local files, `false` by default.
+    ///
+    /// **NOTE**: `data:` urls are always allowed.
+    pub fn allow_file_urls(mut self, allow_file_urls: bool) -> FetchClientBuilder {
+        self.network.allow_file_urls = allow_file_urls;
+        self
+    }
+
+    /// Sets the transport requests are sent by, like a `MockFetch`, instead of the network.
+    ///
+    /// **NOTE**: The user agent, proxies, TLS options and `allow_file_urls` only apply to the
+    /// default transport, another transport also receives the requests of `data:` and `file:` urls.
+    pub fn transport(mut self, transport: impl Transport + 'static) -> FetchClientBuilder {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Adds an interceptor, which runs after the interceptors added before it.
+    pub fn interceptor(mut self, interceptor: impl Interceptor + 'static) -> FetchClientBuilder {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// Sets the cookie jar of requests which do not set their own.
+    pub fn cookie_jar(mut self, cookie_jar: CookieJar) -> FetchClientBuilder {
+        self.cookie_jar = Some(cookie_jar);
+        self
+    }
+
+    /// Sets the http cache of requests which do not set their own.
+    pub fn http_cache(mut self, http_cache: HttpCache) -> FetchClientBuilder {
+        self.http_cache = Some(http_cache);
+        self
+    }
+
+    /// Sets the retry policy of requests which do not set their own.
+    pub fn retry(mut self, retry: RetryPolicy) -> FetchClientBuilder {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Sets which response statuses are successful for requests which do not set their own.
+    pub fn validate_status(mut self, validate_status: ValidateStatus) -> FetchClientBuilder {
+        self.validate_status = Some(validate_status);
+        self
+    }
+
+    /// Creates the client.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - the base url is not a valid absolute url
+    /// - the user agent is not a valid header value
+    /// - a proxy url is not a valid url of a supported scheme
+    /// - the TLS options are invalid
+    /// - the TLS backend can not be initialized
+    pub fn build(self) -> Result<FetchClient> {
+        let base_url = match self.base_url.as_deref().map(Url::parse) {
+            Some(Ok(base_url)) => Some(base_url),
+            Some(Err(error)) => return Err(Error::type_error(format!("invalid base url: {}", error))),
+            None => None
+        };
+        let (transport, network) = match self.transport {
+            Some(transport) => (transport, None),
+            None => (self.network.transport(None)?, Some(self.network))
+        };
+        Ok(FetchClient {
+            transport,
+            network,
+            tls_transports: Arc::default(),
+            interceptors: self.interceptors,
+            base_url,
+            headers: self.headers,
+            timeout: self.timeout,
+            cookie_jar: self.cookie_jar,
+            http_cache: self.http_cache,
+            retry: self.retry,
+            validate_status: self.validate_status
+        })
+    }
+}
+
+impl NetworkOptions {
+    /// Creates the transport sending requests over the network, with `tls` instead of the TLS
+    /// options of the client when it is set.
+    fn transport(&self, tls: Option<&TlsOptions>) -> Result<Arc<dyn Transport>> {
+        let mut builder = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if self.proxies.is_empty() && self.env_proxy == Some(false) {
+            builder = builder.no_proxy();
+        }
+        for proxy in &self.proxies {
+            builder = builder.proxy(proxy.clone().into_reqwest()?);
+        }
+        if let Some(tls) = tls.or(self.tls.as_ref()) {
+            builder = builder.tls_backend_preconfigured(tls.client_config()?);
+        }
+        match builder.build() {
+            Ok(client) => {
+                let transport = Arc::new(HttpTransport::new(client));
+                Ok(LocalTransport::wrap(transport, self.allow_file_urls))
+            },
+            Err(error) => Err(Error::from(error))
+        }
+    }
+}
+
+/// The client of the free `fetch` and `fetch_with` functions, created on first use.
+///
+/// A client which fails to be created is created again by the next call, so each request sent
+/// without it fails with its own error.
+pub(crate) fn global() -> Result<&'static FetchClient> {
+    static CLIENT: OnceLock<FetchClient> = OnceLock::new();
+    if let Some(client) = CLIENT.get() {
+        return Ok(client);
+    }
+    let client = FetchClient::builder().build()?;
+    Ok(CLIENT.get_or_init(|| client))
+}
+
+/// `client`, or the global client when it is `None`.
+pub(crate) fn or_global(client: Option<&FetchClient>) -> Result<&FetchClient> {
+    match client {
+        Some(client) => Ok(client),
+        None => global()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{response, TestServer};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn defaults_apply_to_every_request() {
+        let server = TestServer::start(|request| response(200, &[], request.target.as_bytes())).await;
+        let client = FetchClient::builder()
+            .base_url(&server.url("/api/"))
+            .default_headers(Headers::from([("x-team", "core"), ("accept", "text/plain")]))
+            .user_agent("js_lib tests")
+            .build()
+            .unwrap();
+        assert_eq!(client.fetch("/users?page=2").await.unwrap().text().await.unwrap(), "/api/users?page=2");
+        client.fetch_with(&server.url("/absolute"), RequestInit {
+            headers: Headers::from([("accept", "application/json")]),
+            ..Default::default()
+        }).await.unwrap();
+        let received = server.received();
+        assert_eq!(received[1].target, "/absolute");
+        assert_eq!(received[1].header("x-team"), Some("core"));
+        assert_eq!(received[1].header("accept"), Some("application/json"));
+        assert_eq!(received[1].header("user-agent"), Some("js_lib tests"));
+        assert!(FetchClient::builder().base_url("not a url").build().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn timeout_covers_the_body() {
+        let server = TestServer::start_parts(|_| vec![
+            (Duration::ZERO, b"HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\nsl".to_vec()),
+            (Duration::from_secs(5), b"ow".to_vec())
+        ]).await;
+        let client = FetchClient::builder().timeout(Duration::from_millis(200)).build().unwrap();
+        let response = client.fetch(&server.url("/")).await.unwrap();
+        assert!(matches!(response.text().await, Err(Error::TimeoutError { .. })));
+    }
+
+    #[test]
+    fn only_the_recently_used_tls_transports_are_kept() {
+        let client = FetchClient::builder().build().unwrap();
+        let network = client.network.clone().unwrap();
+        let options = (0..=MAX_TLS_TRANSPORTS as u8)
+            .map(|index| TlsOptions::new().pin_spki_sha256([index; 32]))
+            .collect::<Vec<_>>();
+        let first = client.tls_transport(&network, &options[0]).unwrap();
+        let second = client.tls_transport(&network, &options[1]).unwrap();
+        for tls in &options[2..MAX_TLS_TRANSPORTS] {
+            client.tls_transport(&network, tls).unwrap();
+        }
+        // Using the first options again makes the second ones the least recently used.
+        assert!(Arc::ptr_eq(&first, &client.tls_transport(&network, &options[0]).unwrap()));
+        client.tls_transport(&network, &options[MAX_TLS_TRANSPORTS]).unwrap();
+        assert_eq!(client.tls_transports.lock().unwrap().len(), MAX_TLS_TRANSPORTS);
+        assert!(Arc::ptr_eq(&first, &client.tls_transport(&network, &options[0]).unwrap()));
+        assert!(!Arc::ptr_eq(&second, &client.tls_transport(&network, &options[1]).unwrap()));
+    }
+}
@@ -0,0 +1,459 @@
+//! The `CookieJar` which keeps cookies across `fetch` calls.
+
+use crate::date::{parse_date, unix_time};
+use crate::{Error, Result};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Which requests send and store cookies, mirroring the javascript `credentials` option.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RequestCredentials {
+    /// Cookies are never sent or stored.
+    Omit,
+    /// Cookies are only sent and stored while the request stays on the origin of the url it was
+    /// made to, so a redirect to another origin carries none.
+    #[default]
+    SameOrigin,
+    /// Cookies are always sent and stored.
+    Include
+}
+
+impl RequestCredentials {
+    pub(crate) fn allows(self, initial_url: &Url, url: &Url) -> bool {
+        match self {
+            RequestCredentials::Omit => false,
+            RequestCredentials::SameOrigin => initial_url.origin() == url.origin(),
+            RequestCredentials::Include => true
+        }
+    }
+}
+
+/// A store of cookies shared by the requests using it, parsed from `set-cookie` headers by the
+/// rules of RFC 6265.
+///
+/// A cookie whose `Domain` attribute is a public suffix, like `com` or `github.io`, is rejected
+/// unless it is set by that exact host. Clones of a jar share the same cookies. A jar can be
+/// saved to and loaded from a json file so sessions survive across runs.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{fetch_with, CookieJar, RequestCredentials, RequestInit};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let jar = CookieJar::new();
+/// for _ in 0..2 {
+///     fetch_with("https://www.google.com/", RequestInit {
+///         credentials: RequestCredentials::Include,
+///         cookie_jar: Some(jar.clone()),
+///         ..Default::default()
+///     }).await?;
+/// }
+/// jar.save("cookies.json")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Arc<Mutex<Vec<Cookie>>>
+}
+
+/// A cookie stored in a `CookieJar`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    host_only: bool,
+    path: String,
+    expires: Option<i64>,
+    secure: bool,
+    http_only: bool,
+    created: u64
+}
+
+impl CookieJar {
+    /// Creates an empty jar.
+    pub fn new() -> CookieJar {
+        CookieJar::default()
+    }
+
+    /// Loads a jar saved by `save`.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - there is an error reading the file
+    /// - there is an error parsing the file as json
+    pub fn load(path: impl AsRef<Path>) -> Result<CookieJar> {
+        let json = match std::fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(error) => return Err(Error::from(error))
+        };
+        let cookies = crate::from_json::<Vec<Cookie>>(&json)?;
+        Ok(CookieJar { cookies: Arc::new(Mutex::new(cookies)) })
+    }
+
+    /// Saves every cookie which has not expired to a json file, including session cookies.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - there is an error writing the file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let now = unix_time();
+        let mut cookies = self.cookies.lock().unwrap();
+        cookies.retain(|cookie| !cookie.expired(now));
+        let json = match serde_json::to_string_pretty(&*cookies) {
+            Ok(json) => json,
+            Err(error) => return Err(Error::from(error))
+        };
+        match std::fs::write(path, json) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(Error::from(error))
+        }
+    }
+
+    /// Stores a cookie from a `set-cookie` header value received from `url`.
+    ///
+    /// Cookies which are malformed, or which `url` is not allowed to set, are ignored.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - `url` is not a valid absolute url
+    pub fn set_cookie(&self, url: &str, set_cookie: &str) -> Result<()> {
+        match Url::parse(url) {
+            Ok(url) => {
+                self.store(&url, set_cookie);
+                Ok(())
+            },
+            Err(error) => Err(Error::type_error(format!("invalid url {:?}: {}", url, error)))
+        }
+    }
+
+    /// The cookies which would be sent to `url`, in the order they are sent.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - `url` is not a valid absolute url
+    pub fn cookies(&self, url: &str) -> Result<Vec<Cookie>> {
+        match Url::parse(url) {
+            Ok(url) => Ok(self.matching(&url)),
+            Err(error) => Err(Error::type_error(format!("invalid url {:?}: {}", url, error)))
+        }
+    }
+
+    /// Removes every cookie.
+    pub fn clear(&self) {
+        self.cookies.lock().unwrap().clear();
+    }
+
+    /// The `cookie` header value to send to `url`, if any cookie matches it.
+    pub(crate) fn header_for(&self, url: &Url) -> Option<String> {
+        let cookies = self.matching(url);
+        if cookies.is_empty() {
+            return None;
+        }
+        let pairs = cookies.iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect::<Vec<_>>();
+        Some(pairs.join("; "))
+    }
+
+    /// Stores a cookie from a `set-cookie` header value, following RFC 6265 section 5.3.
+    pub(crate) fn store(&self, url: &Url, set_cookie: &str) {
+        let Some(host) = host_of(url) else {
+            return;
+        };
+        let Some(mut cookie) = parse(set_cookie, &host, url.path()) else {
+            return;
+        };
+        if !cookie.host_only && !domain_matches(&host, &cookie.domain) {
+            return;
+        }
+        if cookie.secure && url.scheme() != "https" {
+            return;
+        }
+        let now = unix_time();
+        let mut cookies = self.cookies.lock().unwrap();
+        cookies.retain(|old| {
+            let replaced = old.name == cookie.name && old.domain == cookie.domain && old.path == cookie.path;
+            if replaced {
+                cookie.created = old.created;
+            }
+            !replaced && !old.expired(now)
+        });
+        if !cookie.expired(now) {
+            cookies.push(cookie);
+        }
+    }
+
+    /// The cookies matching `url`, longest paths first, following RFC 6265 section 5.4.
+    fn matching(&self, url: &Url) -> Vec<Cookie> {
+        let Some(host) = host_of(url) else {
+            return Vec::new();
+        };
+        let now = unix_time();
+        let mut cookies = self.cookies.lock().unwrap().iter()
+            .filter(|cookie| !cookie.expired(now))
+            .filter(|cookie| match cookie.host_only {
+                true => host == cookie.domain,
+                false => domain_matches(&host, &cookie.domain)
+            })
+            .filter(|cookie| path_matches(url.path(), &cookie.path))
+            .filter(|cookie| !cookie.secure || url.scheme() == "https")
+            .cloned()
+            .collect::<Vec<_>>();
+        cookies.sort_by(|a, b| b.path.len().cmp(&a.path.len()).then(a.created.cmp(&b.created)));
+        cookies
+    }
+}
+
+impl Cookie {
+    /// The name of the cookie.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The value of the cookie.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The domain the cookie is sent to, including its subdomains unless `host_only` is true.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Whether the cookie is only sent to exactly its domain, as it had no `Domain` attribute.
+    pub fn host_only(&self) -> bool {
+        self.host_only
+    }
+
+    /// The path the cookie is sent to, including its sub paths.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// When the cookie expires, or `None` for a session cookie.
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.expires.map(|seconds| match u64::try_from(seconds) {
+            Ok(seconds) => UNIX_EPOCH + Duration::from_secs(seconds),
+            Err(_) => UNIX_EPOCH
+        })
+    }
+
+    /// Whether the cookie is only sent over https.
+    pub fn secure(&self) -> bool {
+        self.secure
+    }
+
+    /// Whether the cookie had the `HttpOnly` attribute.
+    pub fn http_only(&self) -> bool {
+        self.http_only
+    }
+
+    fn expired(&self, now: i64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// Parses a `set-cookie` header value received from `host` and `request_path`, following RFC
+/// 6265 section 5.2.
+fn parse(set_cookie: &str, host: &str, request_path: &str) -> Option<Cookie> {
+    let mut parts = set_cookie.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let (name, value) = (name.trim(), value.trim());
+    if name.is_empty() {
+        return None;
+    }
+    let mut cookie = Cookie {
+        name: name.to_string(),
+        value: value.to_string(),
+        domain: host.to_string(),
+        host_only: true,
+        path: default_path(request_path),
+        expires: None,
+        secure: false,
+        http_only: false,
+        created: unix_time_nanos()
+    };
+    let mut max_age = None;
+    for attribute in parts {
+        let (key, value) = match attribute.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (attribute.trim(), "")
+        };
+        match key.to_ascii_lowercase().as_str() {
+            "expires" => {
+                if let Some(expires) = parse_date(value) {
+                    cookie.expires = Some(expires);
+                }
+            },
+            "max-age" => {
+                let digits = value.strip_prefix('-').unwrap_or(value);
+                if !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit()) {
+                    max_age = Some(match value.parse::<i64>() {
+                        Ok(seconds) => seconds,
+                        Err(_) if value.starts_with('-') => i64::MIN,
+                        Err(_) => i64::MAX
+                    });
+                }
+            },
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                // A public suffix is only accepted as the host itself, which keeps the cookie
+                // host-only, as in step 5 of RFC 6265 section 5.3.
+                if is_public_suffix(&domain) && domain != host {
+                    return None;
+                }
+                if domain != host {
+                    if host.parse::<IpAddr>().is_ok() {
+                        return None;
+                    }
+                    cookie.host_only = false;
+                }
+                cookie.domain = domain;
+            },
+            "path" if value.starts_with('/') => cookie.path = value.to_string(),
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            _ => {}
+        }
+    }
+    if let Some(max_age) = max_age {
+        cookie.expires = Some(if max_age <= 0 { i64::MIN } else { unix_time().saturating_add(max_age) });
+    }
+    Some(cookie)
+}
+
+/// The lowercased host of a url, without the brackets of an ipv6 address.
+fn host_of(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    Some(host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase())
+}
+
+/// The directory of the request path, used when a cookie has no `Path` attribute.
+fn default_path(request_path: &str) -> String {
+    match request_path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => request_path[..index].to_string()
+    }
+}
+
+/// Whether `domain` is a single label or a known public suffix, like `com` or `co.uk`, which
+/// a cookie can not be set for.
+fn is_public_suffix(domain: &str) -> bool {
+    let known_suffix = |suffix: psl::Suffix<'_>| suffix.is_known() && suffix == domain.as_bytes();
+    !domain.contains('.') || psl::suffix(domain.as_bytes()).is_some_and(known_suffix)
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<IpAddr>().is_err())
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+/// A creation timestamp fine enough to order cookies set in the same second.
+fn unix_time_nanos() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_nanos() as u64,
+        Err(_) => 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{response, TestServer};
+    use crate::{fetch_with, RequestInit};
+
+    #[test]
+    fn parse_attributes() {
+        let set_cookie = "sid=abc; Domain=.Example.com; Path=/api; Secure; HttpOnly";
+        let cookie = parse(set_cookie, "www.example.com", "/login").unwrap();
+        let fields = (cookie.name(), cookie.value(), cookie.domain(), cookie.path());
+        assert_eq!(fields, ("sid", "abc", "example.com", "/api"));
+        assert!(!cookie.host_only() && cookie.secure() && cookie.http_only());
+        let expires = "a=1; Expires=Wed, 09 Jun 2021 10:18:14 GMT";
+        let cookie = parse(expires, "example.com", "/docs/page").unwrap();
+        assert_eq!(cookie.expires, Some(1623233894));
+        assert_eq!(cookie.path(), "/docs");
+        let cookie = parse(&format!("{}; Max-Age=0", expires), "example.com", "/").unwrap();
+        assert_eq!(cookie.expires, Some(i64::MIN));
+        assert!(parse("no-equals-sign", "example.com", "/").is_none());
+        assert!(parse("a=1; Domain=other.com", "127.0.0.1", "/").is_none());
+    }
+
+    #[test]
+    fn public_suffix_domains_are_rejected() {
+        assert!(parse("a=1; Domain=com", "example.com", "/").is_none());
+        assert!(parse("a=1; Domain=.co.uk", "www.example.co.uk", "/").is_none());
+        assert!(parse("a=1; Domain=github.io", "evil.github.io", "/").is_none());
+        assert!(parse("a=1; Domain=intranet", "wiki.intranet", "/").is_none());
+        let cookie = parse("a=1; Domain=example.co.uk", "www.example.co.uk", "/").unwrap();
+        assert_eq!((cookie.domain(), cookie.host_only()), ("example.co.uk", false));
+        let cookie = parse("a=1; Domain=github.io", "github.io", "/").unwrap();
+        assert_eq!((cookie.domain(), cookie.host_only()), ("github.io", true));
+        let cookie = parse("a=1; Domain=localhost", "localhost", "/").unwrap();
+        assert!(cookie.host_only());
+    }
+
+    #[test]
+    fn match_domains_and_paths() {
+        let jar = CookieJar::new();
+        jar.set_cookie("https://www.example.com/", "host=1").unwrap();
+        jar.set_cookie("https://www.example.com/", "wide=2; Domain=example.com").unwrap();
+        jar.set_cookie("https://www.example.com/", "deep=3; Path=/api").unwrap();
+        jar.set_cookie("https://www.example.com/", "evil=4; Domain=other.com").unwrap();
+        let header = |url: &str| jar.header_for(&Url::parse(url).unwrap());
+        assert_eq!(header("https://www.example.com/api/users").as_deref(), Some("deep=3; host=1; wide=2"));
+        assert_eq!(header("https://www.example.com/apis").as_deref(), Some("host=1; wide=2"));
+        assert_eq!(header("https://api.example.com/").as_deref(), Some("wide=2"));
+        assert_eq!(header("https://other.com/"), None);
+        jar.set_cookie("https://www.example.com/", "host=gone; Max-Age=-1").unwrap();
+        assert_eq!(header("https://www.example.com/").as_deref(), Some("wide=2"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cookies_survive_across_fetches_and_files() {
+        let server = TestServer::start(|request| match request.target.as_str() {
+            "/login" => {
+                response(200, &[("set-cookie", "session=42; Path=/"), ("set-cookie", "theme=dark")], b"")
+            },
+            _ => response(200, &[], b"")
+        }).await;
+        let jar = CookieJar::new();
+        let init = |credentials| {
+            RequestInit { credentials, cookie_jar: Some(jar.clone()), ..Default::default() }
+        };
+        fetch_with(&server.url("/login"), init(RequestCredentials::SameOrigin)).await.unwrap();
+        fetch_with(&server.url("/profile"), init(RequestCredentials::Include)).await.unwrap();
+        fetch_with(&server.url("/profile"), init(RequestCredentials::Omit)).await.unwrap();
+        let received = server.received();
+        assert_eq!(received[1].header("cookie"), Some("session=42; theme=dark"));
+        assert_eq!(received[2].header("cookie"), None);
+
+        let path = std::env::temp_dir().join(format!("js_lib_cookies_{}.json", std::process::id()));
+        jar.save(&path).unwrap();
+        let loaded = CookieJar::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.cookies(&server.url("/")).unwrap(), jar.cookies(&server.url("/")).unwrap());
+    }
+}
@@ -0,0 +1,104 @@
+//! Parsing of the dates in cookies and http headers.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Parses a date into seconds since the unix epoch, following RFC 6265 section 5.1.1.
+///
+/// The algorithm is lenient enough to read every http date format as well as cookie dates.
+pub(crate) fn parse_date(text: &str) -> Option<i64> {
+    let is_delimiter = |c: char| matches!(c, '\t' | ' '..='/' | ';'..='@' | '['..='`' | '{'..='~');
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+    for token in text.split(is_delimiter).filter(|token| !token.is_empty()) {
+        if time.is_none() {
+            if let Some(found) = parse_time(token) {
+                time = Some(found);
+                continue;
+            }
+        }
+        if day.is_none() {
+            if let Some(found) = leading_digits(token, 1, 2) {
+                day = Some(found);
+                continue;
+            }
+        }
+        if month.is_none() {
+            let months = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+            let prefix = token.get(..3).map(str::to_ascii_lowercase);
+            if let Some(index) = months.iter().position(|month| Some(*month) == prefix.as_deref()) {
+                month = Some(index as i64 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some(found) = leading_digits(token, 2, 4) {
+                year = Some(found);
+            }
+        }
+    }
+    let ((hour, minute, second), day, month, mut year) = (time?, day?, month?, year?);
+    if (70..=99).contains(&year) {
+        year += 1900;
+    } else if (0..=69).contains(&year) {
+        year += 2000;
+    }
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Parses a `hh:mm:ss` time, each field being one or two digits.
+fn parse_time(token: &str) -> Option<(i64, i64, i64)> {
+    let mut fields = token.splitn(3, ':');
+    let hour = fields.next()?;
+    let minute = fields.next()?;
+    let second = fields.next()?;
+    let exact = |field: &str| match field.len() {
+        1 | 2 if field.bytes().all(|byte| byte.is_ascii_digit()) => field.parse().ok(),
+        _ => None
+    };
+    Some((exact(hour)?, exact(minute)?, leading_digits(second, 1, 2)?))
+}
+
+/// Parses between `min` and `max` leading digits, which may only be followed by a non-digit.
+fn leading_digits(token: &str, min: usize, max: usize) -> Option<i64> {
+    let count = token.bytes().take_while(u8::is_ascii_digit).count();
+    if (min..=max).contains(&count) {
+        token[..count].parse().ok()
+    } else {
+        None
+    }
+}
+
+/// The days since the unix epoch of a date in the proleptic gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The current time in seconds since the unix epoch.
+pub(crate) fn unix_time() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(_) => 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_http_date_formats() {
+        let expected = Some(784111777);
+        assert_eq!(parse_date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse_date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse_date("Sun Nov  6 08:49:37 1994"), expected);
+        assert_eq!(parse_date("06 Nov 1994 25:49:37"), None);
+        assert_eq!(parse_date("not a date"), None);
+    }
+}
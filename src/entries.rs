@@ -0,0 +1,19 @@
+//! The ordered lists of named entries behind `Headers`, `FormData` and `URLSearchParams`.
+
+/// Sets the first entry named `name` to `value` and removes the others, or appends an entry when
+/// there is none.
+pub(crate) fn set<V>(list: &mut Vec<(String, V)>, name: &str, value: V) {
+    match list.iter().position(|(key, _)| key == name) {
+        Some(index) => {
+            list[index].1 = value;
+            let mut first = true;
+            list.retain(|(key, _)| {
+                if key != name {
+                    return true;
+                }
+                std::mem::replace(&mut first, false)
+            });
+        },
+        None => list.push((name.to_string(), value))
+    }
+}
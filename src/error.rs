@@ -0,0 +1,345 @@
+//! The `Error` of every fallible `js_lib` function, with kinds modeled on javascript's
+//! `DOMException` names.
+
+use crate::{Method, RetryAttempt};
+use std::fmt;
+
+/// The cause of an `Error`, any error which can be sent between threads.
+type Cause = Box<dyn std::error::Error + Send + Sync>;
+
+/// The Errors that may occur.
+///
+/// The kinds mirror the errors javascript's `fetch` rejects with. Every kind carries an
+/// `ErrorContext` with the url, method and status of the request it happened in, when they
+/// are known, and the error which caused it, available through `std::error::Error::source`.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{fetch, Error};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// match fetch("https://www.google.com/").await {
+///     Ok(response) => println!("{}", response.status()),
+///     Err(Error::TimeoutError { .. }) => println!("timed out"),
+///     Err(error) => println!("{} failed: {}", error.url().unwrap_or("?"), error)
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub enum Error {
+    /// An api was used incorrectly or was given an invalid input, like an invalid url or
+    /// reading a body which has already been used.
+    TypeError {
+        /// What was wrong.
+        message: String,
+        /// Where the error happened.
+        context: Box<ErrorContext>
+    },
+    /// The request was aborted by its `AbortSignal`.
+    AbortError {
+        /// The reason the signal was aborted with.
+        message: String,
+        /// Where the error happened.
+        context: Box<ErrorContext>
+    },
+    /// The request took too long, because a timeout signal or the timeout of a `FetchClient`
+    /// elapsed, or the connection timed out.
+    TimeoutError {
+        /// What timed out.
+        message: String,
+        /// Where the error happened.
+        context: Box<ErrorContext>
+    },
+    /// The request could not be sent, or its response could not be received, like a refused
+    /// connection or an io error reading a file.
+    NetworkError {
+        /// What failed.
+        message: String,
+        /// Where the error happened.
+        context: Box<ErrorContext>
+    },
+    /// A json input could not be parsed or did not match the expected type.
+    SyntaxError {
+        /// What was wrong.
+        message: String,
+        /// The line of the input the error is at, starting from 1.
+        line: usize,
+        /// The column of the line the error is at, starting from 1.
+        column: usize,
+        /// Where the error happened.
+        context: Box<ErrorContext>
+    },
+    /// The response has a status rejected by the `ValidateStatus` of the request.
+    HttpStatus {
+        /// The http status code, e.g. `404`.
+        status: u16,
+        /// The url of the response.
+        url: String,
+        /// The start of the body, like the message of an error page.
+        body_snippet: String,
+        /// Where the error happened.
+        context: Box<ErrorContext>
+    }
+}
+
+/// The request an `Error` happened in, and the error which caused it.
+///
+/// It is boxed inside each kind, so a `Result` stays small.
+#[derive(Debug, Default)]
+pub struct ErrorContext {
+    url: Option<String>,
+    method: Option<Method>,
+    status: Option<u16>,
+    attempts: Vec<RetryAttempt>,
+    cause: Option<Cause>
+}
+
+impl Error {
+    /// Creates a `TypeError`, e.g. for a `ReadableStream` whose source is invalid.
+    pub fn type_error(message: impl Into<String>) -> Error {
+        Error::TypeError { message: message.into(), context: Box::default() }
+    }
+
+    /// Creates a `NetworkError` caused by `cause`, e.g. for a `ReadableStream` whose source
+    /// failed.
+    pub fn network_error(message: impl Into<String>, cause: impl Into<Cause>) -> Error {
+        let error = Error::NetworkError { message: message.into(), context: Box::default() };
+        error.with_cause(cause)
+    }
+
+    pub(crate) fn abort_error(reason: String) -> Error {
+        Error::AbortError { message: reason, context: Box::default() }
+    }
+
+    pub(crate) fn timeout_error(message: String) -> Error {
+        Error::TimeoutError { message, context: Box::default() }
+    }
+
+    pub(crate) fn http_status(status: u16, url: String, body_snippet: String) -> Error {
+        Error::HttpStatus { status, url, body_snippet, context: Box::default() }
+    }
+
+    /// The name of the kind of error, e.g. `"NetworkError"`, as javascript's `error.name`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Error::TypeError { .. } => "TypeError",
+            Error::AbortError { .. } => "AbortError",
+            Error::TimeoutError { .. } => "TimeoutError",
+            Error::NetworkError { .. } => "NetworkError",
+            Error::SyntaxError { .. } => "SyntaxError",
+            Error::HttpStatus { .. } => "HttpStatus"
+        }
+    }
+
+    /// The url of the request the error happened in, if known.
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            Error::HttpStatus { url, .. } => Some(url),
+            _ => self.context().url.as_deref()
+        }
+    }
+
+    /// The method of the request the error happened in, if known.
+    pub fn method(&self) -> Option<&Method> {
+        self.context().method.as_ref()
+    }
+
+    /// The status of the response the error happened in, if one was received.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::HttpStatus { status, .. } => Some(*status),
+            _ => self.context().status
+        }
+    }
+
+    /// The attempts which failed before this error, when the request was retried by its
+    /// `RetryPolicy`.
+    pub fn attempts(&self) -> &[RetryAttempt] {
+        &self.context().attempts
+    }
+
+    fn context(&self) -> &ErrorContext {
+        match self {
+            Error::TypeError { context, .. }
+            | Error::AbortError { context, .. }
+            | Error::TimeoutError { context, .. }
+            | Error::NetworkError { context, .. }
+            | Error::SyntaxError { context, .. }
+            | Error::HttpStatus { context, .. } => context
+        }
+    }
+
+    fn context_mut(&mut self) -> &mut ErrorContext {
+        match self {
+            Error::TypeError { context, .. }
+            | Error::AbortError { context, .. }
+            | Error::TimeoutError { context, .. }
+            | Error::NetworkError { context, .. }
+            | Error::SyntaxError { context, .. }
+            | Error::HttpStatus { context, .. } => context
+        }
+    }
+
+    /// Sets the request the error happened in, unless a more specific one is already set.
+    pub(crate) fn with_request(mut self, method: &Method, url: &str) -> Error {
+        let context = self.context_mut();
+        context.method.get_or_insert_with(|| method.clone());
+        context.url.get_or_insert_with(|| url.to_string());
+        self
+    }
+
+    /// Sets the response the error happened in, unless one is already set.
+    pub(crate) fn with_response(mut self, status: u16, url: &str) -> Error {
+        let context = self.context_mut();
+        context.status.get_or_insert(status);
+        context.url.get_or_insert_with(|| url.to_string());
+        self
+    }
+
+    pub(crate) fn with_attempts(mut self, attempts: Vec<RetryAttempt>) -> Error {
+        self.context_mut().attempts = attempts;
+        self
+    }
+
+    pub(crate) fn with_cause(mut self, cause: impl Into<Cause>) -> Error {
+        self.context_mut().cause = Some(cause.into());
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TypeError { message, .. }
+            | Error::AbortError { message, .. }
+            | Error::TimeoutError { message, .. }
+            | Error::NetworkError { message, .. } => write!(f, "{}: {}", self.name(), message)?,
+            Error::SyntaxError { message, line, column, .. } => {
+                write!(f, "SyntaxError: {} at line {} column {}", message, line, column)?
+            },
+            Error::HttpStatus { status, .. } => write!(f, "HttpStatus: the response status is {}", status)?
+        }
+        match (self.method(), self.url()) {
+            (Some(method), Some(url)) => write!(f, " ({} {})", method, url)?,
+            (None, Some(url)) => write!(f, " ({})", url)?,
+            _ => {}
+        }
+        if !self.attempts().is_empty() {
+            write!(f, ", after {} failed attempts", self.attempts().len())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.context().cause {
+            Some(cause) => Some(&**cause),
+            None => None
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        let url = error.url().map(|url| url.to_string());
+        let status = error.status().map(|status| status.as_u16());
+        // The url is kept in the context rather than repeated in the message.
+        let error = error.without_url();
+        let message = describe(&error);
+        let mut error = if error.is_timeout() {
+            Error::timeout_error(message).with_cause(error)
+        } else {
+            Error::network_error(message, error)
+        };
+        let context = error.context_mut();
+        context.url = url;
+        context.status = status;
+        error
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        let message = error.to_string();
+        // The message of serde_json ends with the position, which is kept in its own fields.
+        let message = match message.rfind(" at line ") {
+            Some(index) => message[..index].to_string(),
+            None => message
+        };
+        let (line, column) = (error.line(), error.column());
+        Error::SyntaxError { message, line, column, context: Box::default() }.with_cause(error)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        let message = error.to_string();
+        match error.kind() {
+            std::io::ErrorKind::TimedOut => Error::timeout_error(message).with_cause(error),
+            _ => Error::network_error(message, error)
+        }
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(error: tokio_tungstenite::tungstenite::Error) -> Self {
+        match error {
+            tokio_tungstenite::tungstenite::Error::Io(error) => Error::from(error),
+            error => Error::network_error(error.to_string(), error)
+        }
+    }
+}
+
+impl From<tokio::time::error::Elapsed> for Error {
+    fn from(error: tokio::time::error::Elapsed) -> Self {
+        Error::timeout_error(error.to_string()).with_cause(error)
+    }
+}
+
+/// The message of `error` followed by those of its sources, which say more than its own.
+fn describe(error: &reqwest::Error) -> String {
+    let sources = std::iter::successors(std::error::Error::source(error), |error| error.source());
+    let mut message = error.to_string();
+    for source in sources {
+        message = format!("{}: {}", message, source);
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fetch, from_json};
+    use std::error::Error as _;
+
+    #[test]
+    fn json_errors_have_a_position() {
+        let error = from_json::<Vec<u32>>("[1,\n  2,\n  x]").unwrap_err();
+        match &error {
+            Error::SyntaxError { line, column, .. } => assert_eq!((*line, *column), (3, 3)),
+            other => panic!("expected a syntax error, got {:?}", other)
+        }
+        assert_eq!(error.to_string(), "SyntaxError: expected value at line 3 column 3");
+        assert!(error.source().is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn network_errors_have_the_request_and_cause() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let error = fetch(&url).await.unwrap_err();
+        assert_eq!(error.name(), "NetworkError");
+        assert_eq!(error.url(), Some(url.as_str()));
+        assert_eq!(error.method(), Some(&Method::GET));
+        assert!(error.to_string().ends_with(&format!("(GET {})", url)));
+        let mut source = error.source();
+        while let Some(cause) = source.filter(|cause| !cause.is::<std::io::Error>()) {
+            source = cause.source();
+        }
+        let io_error = source.and_then(|cause| cause.downcast_ref::<std::io::Error>());
+        assert_eq!(io_error.map(|error| error.kind()), Some(std::io::ErrorKind::ConnectionRefused));
+    }
+}
@@ -0,0 +1,637 @@
+//! The javascript-like `EventSource`, which receives server-sent events over a `fetch`.
+
+use crate::abort;
+use crate::client::{self, FetchClient};
+use crate::{Error, ReadableStream, RequestCache, RequestInit, Result, ValidateStatus};
+use futures_util::stream::{self, BoxStream, Stream, StreamExt};
+use reqwest::Url;
+use std::collections::VecDeque;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// The delay before reconnecting, until the server sets another with a `retry` field.
+const DEFAULT_RECONNECTION_TIME: Duration = Duration::from_secs(3);
+
+/// A connection to a stream of server-sent events, mirroring the javascript `EventSource`.
+///
+/// The events of the `text/event-stream` response are read as a `Stream` of `MessageEvent`s.
+/// When the connection is lost the source reconnects after the delay of the last `retry` field,
+/// sending the id of the last event in the `last-event-id` header, so the server can resume the
+/// stream. A response which is not a `200` with the `text/event-stream` content type closes the
+/// source instead, and is the last item of the stream, like any error other than a network
+/// error or a timeout. A url which is invalid or not http or https closes the source before it
+/// connects, so its stream only holds that error.
+///
+/// The `onopen`, `onmessage` and `onerror` hooks, and the listeners of other event types, are
+/// called as the stream is read.
+///
+/// # Examples
+///
+/// ```rust
+/// use futures_util::StreamExt;
+/// use js_lib::EventSource;
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let mut source = EventSource::new("https://example.com/updates");
+/// source.onerror(|error| println!("reconnecting after {}", error));
+/// while let Some(event) = source.next().await {
+///     let event = event?;
+///     println!("{}: {}", event.event_type, event.data);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct EventSource {
+    url: String,
+    shared: Arc<Shared>,
+    events: BoxStream<'static, Result<MessageEvent>>
+}
+
+/// An event received by an `EventSource`, mirroring the javascript `MessageEvent`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageEvent {
+    /// The type of the event, set by its `event` field, `"message"` by default.
+    pub event_type: String,
+    /// The data of the event, the lines of its `data` fields joined by `\n`.
+    pub data: String,
+    /// The id of the last event which set one, or an empty string.
+    pub last_event_id: String
+}
+
+/// The state of the connection of an `EventSource`, mirroring its javascript `readyState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSourceState {
+    /// The source is connecting or reconnecting.
+    Connecting,
+    /// The source is connected and receiving events.
+    Open,
+    /// The source was closed, and does not reconnect.
+    Closed
+}
+
+/// The state an `EventSource` shares with the connection reading its events.
+struct Shared {
+    state: Mutex<EventSourceState>,
+    listeners: Mutex<Listeners>
+}
+
+type EventListener = Arc<dyn Fn(&MessageEvent) + Send + Sync>;
+type ErrorListener = Arc<dyn Fn(&Error) + Send + Sync>;
+
+#[derive(Default)]
+struct Listeners {
+    open: Option<Arc<dyn Fn() + Send + Sync>>,
+    message: Option<EventListener>,
+    error: Option<ErrorListener>,
+    events: Vec<(String, EventListener)>
+}
+
+impl EventSource {
+    /// Connects to `url` with the global `FetchClient`.
+    pub fn new(url: &str) -> EventSource {
+        EventSource::with_init(url, RequestInit::default())
+    }
+
+    /// Connects to `url` with the global `FetchClient`, sending the options of `init` with each
+    /// connection, like the headers.
+    ///
+    /// The source is closed once `init.signal` is aborted.
+    pub fn with_init(url: &str, init: RequestInit) -> EventSource {
+        EventSource::connect(None, url, init)
+    }
+
+    /// Connects to `url` with `client`, or the global client when it is `None`.
+    pub(crate) fn connect(client: Option<FetchClient>, url: &str, mut init: RequestInit) -> EventSource {
+        init.cache = RequestCache::NoStore;
+        init.validate_status = Some(ValidateStatus::new(|status| status == 200));
+        let shared = Arc::new(Shared {
+            state: Mutex::new(EventSourceState::Connecting),
+            listeners: Mutex::new(Listeners::default())
+        });
+        let resolved = client.as_ref().map_or(url.to_string(), |client| client.resolve(url));
+        let invalid = match Url::parse(&resolved) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => None,
+            Ok(parsed) => Some(format!("the scheme of {:?} is not http or https", parsed.as_str())),
+            Err(error) => Some(format!("invalid url {:?}: {}", url, error))
+        };
+        if let Some(message) = invalid {
+            // Like the javascript constructor, an invalid url fails before connecting.
+            let error = Error::type_error(message);
+            shared.set_state(EventSourceState::Closed);
+            let failed = shared.clone();
+            let events = stream::once(async move {
+                failed.dispatch_error(&error);
+                Err(error)
+            });
+            return EventSource { url: url.to_string(), shared, events: events.boxed() };
+        }
+        let connection = Connection {
+            client,
+            url: url.to_string(),
+            init,
+            shared: shared.clone(),
+            reconnection_time: DEFAULT_RECONNECTION_TIME,
+            parser: Parser::default(),
+            body: None,
+            connected: false,
+            pending: VecDeque::new()
+        };
+        let events = stream::unfold(connection, |mut connection| async move {
+            let event = connection.next_event().await?;
+            Some((event, connection))
+        });
+        EventSource { url: url.to_string(), shared, events: events.boxed() }
+    }
+
+    /// The url the source connects to.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The state of the connection.
+    pub fn ready_state(&self) -> EventSourceState {
+        *self.shared.state.lock().unwrap()
+    }
+
+    /// Closes the connection, ending the stream of events. A closed source does not reconnect.
+    pub fn close(&mut self) {
+        *self.shared.state.lock().unwrap() = EventSourceState::Closed;
+        self.events = stream::empty().boxed();
+    }
+
+    /// Sets the hook called each time the source connects.
+    pub fn onopen(&mut self, hook: impl Fn() + Send + Sync + 'static) {
+        self.shared.listeners.lock().unwrap().open = Some(Arc::new(hook));
+    }
+
+    /// Sets the hook called with each event of the `"message"` type.
+    pub fn onmessage(&mut self, hook: impl Fn(&MessageEvent) + Send + Sync + 'static) {
+        self.shared.listeners.lock().unwrap().message = Some(Arc::new(hook));
+    }
+
+    /// Sets the hook called with each error, before reconnecting or once the source is closed.
+    pub fn onerror(&mut self, hook: impl Fn(&Error) + Send + Sync + 'static) {
+        self.shared.listeners.lock().unwrap().error = Some(Arc::new(hook));
+    }
+
+    /// Adds a listener called with each event of `event_type`.
+    pub fn add_event_listener(
+        &mut self,
+        event_type: &str,
+        listener: impl Fn(&MessageEvent) + Send + Sync + 'static
+    ) {
+        let listener: EventListener = Arc::new(listener);
+        self.shared.listeners.lock().unwrap().events.push((event_type.to_string(), listener));
+    }
+}
+
+impl Stream for EventSource {
+    type Item = Result<MessageEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_next_unpin(cx)
+    }
+}
+
+impl fmt::Debug for EventSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventSource")
+            .field("url", &self.url)
+            .field("ready_state", &self.ready_state())
+            .finish()
+    }
+}
+
+impl MessageEvent {
+    /// Deserializes the data of the event as json into type T.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - there is an error parsing the data as json
+    pub fn json<T>(&self) -> Result<T>
+        where T: serde::de::DeserializeOwned {
+        crate::from_json(&self.data)
+    }
+}
+
+impl Shared {
+    fn set_state(&self, state: EventSourceState) {
+        let mut current = self.state.lock().unwrap();
+        if *current != EventSourceState::Closed {
+            *current = state;
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        *self.state.lock().unwrap() == EventSourceState::Closed
+    }
+
+    fn dispatch_open(&self) {
+        let hook = self.listeners.lock().unwrap().open.clone();
+        if let Some(hook) = hook {
+            hook();
+        }
+    }
+
+    fn dispatch_error(&self, error: &Error) {
+        let hook = self.listeners.lock().unwrap().error.clone();
+        if let Some(hook) = hook {
+            hook(error);
+        }
+    }
+
+    fn dispatch_event(&self, event: &MessageEvent) {
+        let mut hooks = Vec::new();
+        {
+            let listeners = self.listeners.lock().unwrap();
+            if event.event_type == "message" {
+                hooks.extend(listeners.message.clone());
+            }
+            for (event_type, listener) in &listeners.events {
+                if *event_type == event.event_type {
+                    hooks.push(listener.clone());
+                }
+            }
+        }
+        for hook in hooks {
+            hook(event);
+        }
+    }
+}
+
+/// The connection of an `EventSource`, reading events and reconnecting as the stream is read.
+struct Connection {
+    client: Option<FetchClient>,
+    url: String,
+    init: RequestInit,
+    shared: Arc<Shared>,
+    reconnection_time: Duration,
+    parser: Parser,
+    body: Option<ReadableStream>,
+    connected: bool,
+    pending: VecDeque<MessageEvent>
+}
+
+impl Connection {
+    /// Reads the next event, reconnecting until one is received or the source is closed.
+    async fn next_event(&mut self) -> Option<Result<MessageEvent>> {
+        loop {
+            if self.shared.is_closed() {
+                return None;
+            }
+            if let Some(event) = self.pending.pop_front() {
+                self.shared.dispatch_event(&event);
+                return Some(Ok(event));
+            }
+            let body = match &mut self.body {
+                Some(body) => body,
+                None => {
+                    if self.connected {
+                        let wait = async {
+                            tokio::time::sleep(self.reconnection_time).await;
+                            Ok(())
+                        };
+                        if let Err(error) = abort::abortable(self.init.signal.as_ref(), wait).await {
+                            // A timed out signal closes the source as well.
+                            return self.fail(Failure::Fatal(error)).map(Err);
+                        }
+                    }
+                    self.connected = true;
+                    match self.open().await {
+                        Ok(body) => {
+                            self.body = Some(body);
+                            self.shared.set_state(EventSourceState::Open);
+                            self.shared.dispatch_open();
+                        },
+                        Err(error) => {
+                            if let Some(error) = self.fail(error) {
+                                return Some(Err(error));
+                            }
+                        }
+                    }
+                    continue;
+                }
+            };
+            let error = match body.next().await {
+                Some(Ok(chunk)) => {
+                    self.pending.extend(self.parser.feed(&chunk));
+                    if let Some(retry) = self.parser.retry.take() {
+                        self.reconnection_time = retry;
+                    }
+                    continue;
+                },
+                Some(Err(error)) => error,
+                None => {
+                    Error::network_error("the event stream ended", "the server closed the connection")
+                }
+            };
+            self.body = None;
+            self.parser.reset();
+            if let Some(error) = self.fail(error) {
+                return Some(Err(error));
+            }
+        }
+    }
+
+    /// Sends the request of a connection, failing for a response which is not an event stream.
+    async fn open(&mut self) -> std::result::Result<ReadableStream, Failure> {
+        let mut init = match self.init.try_clone() {
+            Some(init) => init,
+            None => {
+                let error = Error::type_error("an event source can not send a streamed body");
+                return Err(Failure::Fatal(error));
+            }
+        };
+        let last_event_id = self.parser.last_event_id.as_str();
+        let headers = [
+            ("accept", "text/event-stream"),
+            ("cache-control", "no-store"),
+            ("last-event-id", last_event_id)
+        ];
+        for (name, value) in headers.into_iter().filter(|(_, value)| !value.is_empty()) {
+            if let Err(error) = init.headers.set(name, value) {
+                return Err(Failure::Fatal(error));
+            }
+        }
+        let client = match client::or_global(self.client.as_ref()) {
+            Ok(client) => client,
+            Err(error) => return Err(Failure::Fatal(error))
+        };
+        let mut response = match client.fetch_timed(&self.url, init, false).await {
+            Ok(response) => response,
+            Err(error) => return Err(Failure::from(error))
+        };
+        let content_type = response.headers().get("content-type").unwrap_or_default();
+        let essence = content_type.split(';').next().unwrap_or("").trim();
+        if !essence.eq_ignore_ascii_case("text/event-stream") {
+            let message = format!("the content type {:?} is not text/event-stream", content_type);
+            return Err(Failure::Fatal(Error::type_error(message).with_response(200, response.url())));
+        }
+        match response.body() {
+            Some(body) => Ok(body),
+            None => Err(Failure::Fatal(Error::type_error("the event stream has no body")))
+        }
+    }
+
+    /// Reports a failed connection, returning the error which closed the source, if it did.
+    fn fail(&mut self, failure: impl Into<Failure>) -> Option<Error> {
+        match failure.into() {
+            Failure::Retry(error) => {
+                self.shared.set_state(EventSourceState::Connecting);
+                self.shared.dispatch_error(&error);
+                None
+            },
+            Failure::Fatal(error) => {
+                self.shared.set_state(EventSourceState::Closed);
+                self.shared.dispatch_error(&error);
+                Some(error)
+            }
+        }
+    }
+}
+
+/// Why a connection failed, and whether the source reconnects after it.
+enum Failure {
+    Retry(Error),
+    Fatal(Error)
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        match error {
+            // Only a lost connection may succeed when retried, not a request which is refused.
+            Error::NetworkError { .. } | Error::TimeoutError { .. } => Failure::Retry(error),
+            error => Failure::Fatal(error)
+        }
+    }
+}
+
+/// Parses the `text/event-stream` format, as its chunks arrive.
+#[derive(Debug, Default)]
+struct Parser {
+    line: Vec<u8>,
+    after_cr: bool,
+    started: bool,
+    event_type: String,
+    data: String,
+    id: String,
+    last_event_id: String,
+    retry: Option<Duration>
+}
+
+impl Parser {
+    /// Parses a chunk, returning the events it completes.
+    fn feed(&mut self, chunk: &[u8]) -> Vec<MessageEvent> {
+        let mut events = Vec::new();
+        for &byte in chunk {
+            // A line ends with "\r\n", "\r" or "\n", so a "\n" right after a "\r" is skipped.
+            if byte == b'\n' && self.after_cr {
+                self.after_cr = false;
+                continue;
+            }
+            self.after_cr = byte == b'\r';
+            if byte != b'\r' && byte != b'\n' {
+                self.line.push(byte);
+                continue;
+            }
+            let line = String::from_utf8_lossy(&std::mem::take(&mut self.line)).into_owned();
+            let line = if self.started {
+                line.as_str()
+            } else {
+                line.strip_prefix('\u{feff}').unwrap_or(&line)
+            };
+            self.started = true;
+            if let Some(event) = self.process_line(line) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<MessageEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, "")
+        };
+        match field {
+            "event" => self.event_type = value.to_string(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            },
+            "id" if !value.contains('\0') => self.id = value.to_string(),
+            "retry" if !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_digit()) => {
+                self.retry = value.parse().ok().map(Duration::from_millis);
+            },
+            _ => {}
+        }
+        None
+    }
+
+    /// Completes the event of the lines since the last blank line, if it has any data.
+    fn dispatch(&mut self) -> Option<MessageEvent> {
+        self.last_event_id = self.id.clone();
+        let event_type = std::mem::take(&mut self.event_type);
+        let mut data = std::mem::take(&mut self.data);
+        if data.is_empty() {
+            return None;
+        }
+        data.pop();
+        let event_type = if event_type.is_empty() { "message".to_string() } else { event_type };
+        Some(MessageEvent { event_type, data, last_event_id: self.last_event_id.clone() })
+    }
+
+    /// Drops the incomplete event of a lost connection, keeping the last event id.
+    fn reset(&mut self) {
+        self.line.clear();
+        self.after_cr = false;
+        self.started = false;
+        self.event_type.clear();
+        self.data.clear();
+        self.id = self.last_event_id.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{response, TestServer};
+    use crate::{AbortController, RequestRedirect};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn parses_split_chunks_and_every_line_ending() {
+        let mut parser = Parser::default();
+        let mut events = parser.feed(b"\xef\xbb\xbf: a comment\r\nevent: update\rdata: {\"n\":");
+        events.extend(parser.feed(b"1}\r"));
+        events.extend(parser.feed(b"\nid: 7\n\ndata\ndata:second line\nretry: 250\n\nid: 8\n\n"));
+        let event = |event_type: &str, data: &str, last_event_id: &str| MessageEvent {
+            event_type: event_type.into(),
+            data: data.into(),
+            last_event_id: last_event_id.into()
+        };
+        assert_eq!(events, [event("update", r#"{"n":1}"#, "7"), event("message", "\nsecond line", "7")]);
+        assert_eq!(events[0].json::<serde_json::Value>().unwrap()["n"], 1);
+        assert_eq!(parser.retry, Some(Duration::from_millis(250)));
+        assert_eq!(parser.last_event_id, "8");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reconnects_with_the_last_event_id() {
+        let connections = AtomicUsize::new(0);
+        let server = TestServer::start(move |_| {
+            let event_stream = [("content-type", "text/event-stream")];
+            match connections.fetch_add(1, Ordering::SeqCst) {
+                0 => {
+                    response(200, &event_stream, b"retry: 10\nid: 1\ndata: first\n\nevent: ping\ndata: x\n\n")
+                },
+                1 => response(200, &event_stream, b"id: 2\ndata: second\n\ndata: incomplete"),
+                _ => response(204, &[], b"")
+            }
+        }).await;
+        let mut source = EventSource::new(&server.url("/events"));
+        let (opens, pings) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let (open_count, error_log, ping_count) = (opens.clone(), errors.clone(), pings.clone());
+        source.onopen(move || {
+            open_count.fetch_add(1, Ordering::SeqCst);
+        });
+        source.onerror(move |error| error_log.lock().unwrap().push(error.name()));
+        source.add_event_listener("ping", move |_| {
+            ping_count.fetch_add(1, Ordering::SeqCst);
+        });
+        let mut data = Vec::new();
+        while let Some(event) = source.next().await {
+            match event {
+                Ok(event) => data.push(event.data),
+                Err(error) => assert!(matches!(error, Error::HttpStatus { status: 204, .. }))
+            }
+        }
+        assert_eq!(data, ["first", "x", "second"]);
+        assert_eq!(source.ready_state(), EventSourceState::Closed);
+        assert_eq!(opens.load(Ordering::SeqCst), 2);
+        assert_eq!(pings.load(Ordering::SeqCst), 1);
+        assert_eq!(*errors.lock().unwrap(), ["NetworkError", "NetworkError", "HttpStatus"]);
+        let received = server.received();
+        assert_eq!(received[0].header("accept"), Some("text/event-stream"));
+        assert_eq!(received[0].header("last-event-id"), None);
+        assert_eq!(received[1].header("last-event-id"), Some("1"));
+        assert_eq!(received[2].header("last-event-id"), Some("2"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn invalid_requests_end_the_stream_with_one_error() {
+        let mut source = EventSource::new("not a url");
+        let errors = Arc::new(AtomicUsize::new(0));
+        let error_count = errors.clone();
+        source.onerror(move |_| {
+            error_count.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(source.ready_state(), EventSourceState::Closed);
+        let events = tokio::time::timeout(Duration::from_secs(5), source.collect::<Vec<_>>()).await.unwrap();
+        assert!(matches!(&events[..], [Err(Error::TypeError { .. })]));
+        assert_eq!(errors.load(Ordering::SeqCst), 1);
+        let source = EventSource::new("ftp://example.com/events");
+        let events = tokio::time::timeout(Duration::from_secs(5), source.collect::<Vec<_>>()).await.unwrap();
+        assert!(matches!(&events[..], [Err(Error::TypeError { .. })]));
+        // A refused redirect is not retried either.
+        let server = TestServer::start(|_| response(302, &[("location", "/elsewhere")], b"")).await;
+        let init = RequestInit { redirect: RequestRedirect::Error, ..Default::default() };
+        let source = EventSource::with_init(&server.url("/events"), init);
+        let events = tokio::time::timeout(Duration::from_secs(5), source.collect::<Vec<_>>()).await.unwrap();
+        assert!(matches!(&events[..], [Err(Error::TypeError { .. })]));
+        assert_eq!(server.received().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn the_client_timeout_does_not_cut_the_stream() {
+        let connections = AtomicUsize::new(0);
+        let server = TestServer::start_parts(move |_| match connections.fetch_add(1, Ordering::SeqCst) {
+            0 => vec![
+                (Duration::ZERO, b"HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\r\n".to_vec()),
+                (Duration::ZERO, b"retry: 10\ndata: first\n\n".to_vec()),
+                (Duration::from_millis(300), b"data: second\n\n".to_vec())
+            ],
+            _ => vec![(Duration::ZERO, response(204, &[], b""))]
+        }).await;
+        let client = FetchClient::builder().timeout(Duration::from_millis(100)).build().unwrap();
+        let mut source = client.event_source(&server.url("/events"), RequestInit::default());
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let error_log = errors.clone();
+        source.onerror(move |error| error_log.lock().unwrap().push(error.name()));
+        let mut data = Vec::new();
+        while let Some(event) = source.next().await {
+            if let Ok(event) = event {
+                data.push(event.data);
+            }
+        }
+        assert_eq!(data, ["first", "second"]);
+        assert_eq!(*errors.lock().unwrap(), ["NetworkError", "HttpStatus"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn the_signal_closes_the_source_while_waiting_to_reconnect() {
+        let server = TestServer::start(|_| {
+            response(200, &[("content-type", "text/event-stream")], b"retry: 60000\ndata: first\n\n")
+        }).await;
+        let controller = AbortController::new();
+        let init = RequestInit { signal: Some(controller.signal()), ..Default::default() };
+        let mut source = EventSource::with_init(&server.url("/events"), init);
+        assert_eq!(source.next().await.unwrap().unwrap().data, "first");
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            controller.abort("done");
+        });
+        let next = tokio::time::timeout(Duration::from_secs(5), source.next()).await.unwrap();
+        assert!(matches!(next, Some(Err(Error::AbortError { .. }))));
+        assert_eq!(source.ready_state(), EventSourceState::Closed);
+        assert_eq!(server.received().len(), 1);
+    }
+}
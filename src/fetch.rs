@@ -0,0 +1,291 @@
+//! The steps of a `fetch`, from following redirects down to sending each request.
+
+use crate::date::unix_time;
+use crate::scheme;
+use crate::transport::Transport;
+use crate::{Body, Error, Headers, HttpCache, Method, RequestCache, RequestInit, RequestRedirect};
+use crate::{Request, Response, Result};
+use reqwest::Url;
+
+/// The most redirects followed when `RequestInit::max_redirects` is not set, as in the fetch
+/// standard.
+const DEFAULT_MAX_REDIRECTS: usize = 20;
+
+/// The headers describing a body, removed when a redirect drops the body.
+const BODY_HEADERS: [&str; 5] = [
+    "content-encoding",
+    "content-language",
+    "content-location",
+    "content-type",
+    "content-length"
+];
+
+/// The request headers which make a request conditional or partial, so its response is not cached.
+const CONDITIONAL_HEADERS: [&str; 6] = [
+    "if-modified-since",
+    "if-none-match",
+    "if-unmodified-since",
+    "if-match",
+    "if-range",
+    "range"
+];
+
+/// Fetches `url`, following redirects as `init.redirect` asks.
+pub(crate) async fn fetch(transport: &dyn Transport, url: &str, init: RequestInit) -> Result<Response> {
+    let mut url = parse_url(url, None)?;
+    let initial_url = url.clone();
+    let mut method = init.method;
+    let mut headers = init.headers;
+    let mut body = init.body;
+    if let Some(content_type) = body.as_ref().and_then(Body::content_type) {
+        if !headers.has("content-type") {
+            headers.append("content-type", content_type)?;
+        }
+    }
+    let max_redirects = init.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS);
+    let mut redirects = 0;
+    let mut body_streamed = false;
+    loop {
+        // A streamed body can only be sent once, so it is taken rather than cloned.
+        let hop_body = match body.as_ref().map(Body::try_clone) {
+            Some(Some(clone)) => Some(clone),
+            Some(None) => {
+                body_streamed = true;
+                body.take()
+            },
+            None => None
+        };
+        let jar = init.cookie_jar.as_ref().filter(|_| init.credentials.allows(&initial_url, &url));
+        let mut hop_headers = headers.clone();
+        if let Some(cookie) = jar.and_then(|jar| jar.header_for(&url)) {
+            match hop_headers.get("cookie") {
+                Some(own) => hop_headers.set("cookie", &format!("{}; {}", own, cookie))?,
+                None => hop_headers.set("cookie", &cookie)?
+            }
+        }
+        let hop_body = match (hop_body, &init.upload_progress) {
+            (Some(body), Some(listener)) => {
+                let content_length = hop_headers.get("content-length").and_then(|length| length.parse().ok());
+                let (body, size) = body.with_progress(listener, content_length);
+                if let Some(size) = size {
+                    hop_headers.set("content-length", &size.to_string())?;
+                }
+                Some(body)
+            },
+            (body, _) => body
+        };
+        // A local url is read again each time, so its response is never cached.
+        let cache = init.http_cache.as_ref().filter(|_| !scheme::is_local(&url));
+        let response = http_fetch(transport, cache, init.cache, &method, &url, hop_headers, hop_body).await?;
+        if let Some(jar) = jar {
+            for set_cookie in response.headers().get_set_cookie() {
+                jar.store(&url, &set_cookie);
+            }
+        }
+        let status = response.status();
+        let location = response.headers().get("location");
+        let location = match (is_redirect(status), init.redirect, location) {
+            (true, RequestRedirect::Follow, Some(location)) => location,
+            (true, RequestRedirect::Error, Some(_)) => {
+                let message = format!("redirect mode is set to error, but {} redirected", url);
+                return Err(Error::type_error(message).with_response(status, url.as_str()));
+            },
+            _ => {
+                let response = response.with_progress(init.download_progress.as_ref());
+                return Ok(response.finish(redirects > 0, init.signal));
+            }
+        };
+        redirects += 1;
+        if redirects > max_redirects {
+            let message = format!("more than {} redirects were followed", max_redirects);
+            return Err(Error::type_error(message).with_response(status, url.as_str()));
+        }
+        let next_url = parse_url(&location, Some(&url))?;
+        if !matches!(next_url.scheme(), "http" | "https") {
+            let message = format!("a redirect to {} can not be followed, it is not a http url", next_url);
+            return Err(Error::type_error(message).with_response(status, url.as_str()));
+        }
+        let see_other = status == 303 && method != Method::HEAD;
+        if see_other || (matches!(status, 301 | 302) && method == Method::POST) {
+            method = Method::GET;
+            body = None;
+            body_streamed = false;
+            for name in BODY_HEADERS {
+                headers.delete(name);
+            }
+        } else if body_streamed {
+            let message = "a redirect can not be followed with a streamed body";
+            return Err(Error::type_error(message).with_response(status, url.as_str()));
+        }
+        if next_url.origin() != url.origin() {
+            headers.delete("authorization");
+            headers.delete("proxy-authorization");
+        }
+        url = next_url;
+    }
+}
+
+/// Sends one request through the http cache, as the `cache` mode asks.
+async fn http_fetch(
+    transport: &dyn Transport,
+    cache: Option<&HttpCache>,
+    mut mode: RequestCache,
+    method: &Method,
+    url: &Url,
+    mut headers: Headers,
+    body: Option<Body>
+) -> Result<Response> {
+    if mode == RequestCache::Default && CONDITIONAL_HEADERS.iter().any(|name| headers.has(name)) {
+        mode = RequestCache::NoStore;
+    }
+    let not_cached = || {
+        Error::type_error(format!("{} is not cached, and the cache mode is only-if-cached", url))
+    };
+    let cache = match cache {
+        Some(cache) if mode != RequestCache::NoStore && method == Method::GET => cache,
+        _ => {
+            if mode == RequestCache::OnlyIfCached {
+                return Err(not_cached());
+            }
+            let response = network_fetch(transport, method, url, &headers, body).await?;
+            let safe = matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS);
+            if let Some(cache) = cache.filter(|_| !safe && (200..400).contains(&response.status())) {
+                cache.invalidate(url.as_str()).await;
+            }
+            return Ok(response);
+        }
+    };
+    let stored = match mode {
+        RequestCache::Reload => None,
+        _ => cache.lookup(url.as_str(), &headers).await
+    };
+    match stored {
+        Some(stored) if matches!(mode, RequestCache::ForceCache | RequestCache::OnlyIfCached) => {
+            return Ok(stored.into_response());
+        },
+        Some(stored) if mode == RequestCache::Default && stored.is_fresh() => return Ok(stored.into_response()),
+        None if mode == RequestCache::OnlyIfCached => return Err(not_cached()),
+        _ => {}
+    }
+    if !headers.has("cache-control") {
+        match mode {
+            RequestCache::Reload => headers.set("cache-control", "no-cache")?,
+            RequestCache::NoCache => headers.set("cache-control", "max-age=0")?,
+            _ => {}
+        }
+    }
+    if let Some(stored) = &stored {
+        if let Some(etag) = stored.etag() {
+            headers.set("if-none-match", &etag)?;
+        }
+        if let Some(last_modified) = stored.last_modified() {
+            headers.set("if-modified-since", &last_modified)?;
+        }
+    }
+    let request_time = unix_time();
+    let response = network_fetch(transport, method, url, &headers, body).await?;
+    match stored {
+        Some(stored) if response.status() == 304 => {
+            Ok(cache.refresh(stored, response.headers(), request_time).await.into_response())
+        },
+        _ => Ok(cache.store(url.as_str(), &headers, request_time, response))
+    }
+}
+
+/// Sends one request, without following any redirect.
+async fn network_fetch(
+    transport: &dyn Transport,
+    method: &Method,
+    url: &Url,
+    headers: &Headers,
+    body: Option<Body>
+) -> Result<Response> {
+    let request = Request::new(method.clone(), url.to_string(), headers.clone(), body);
+    Ok(transport.send(request).await?.with_url(url.as_str()))
+}
+
+fn parse_url(url: &str, base: Option<&Url>) -> Result<Url> {
+    match Url::options().base_url(base).parse(url) {
+        Ok(url) => Ok(url),
+        Err(error) => Err(Error::type_error(format!("invalid url {:?}: {}", url, error)))
+    }
+}
+
+fn is_redirect(status: u16) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_server::{response, TestServer};
+    use crate::{fetch_with, Headers, Method, ReadableStream, RequestInit, RequestRedirect};
+
+    async fn redirecting_server() -> TestServer {
+        TestServer::start(|request| match request.target.as_str() {
+            "/see-other" => response(303, &[("location", "/done")], b""),
+            "/temporary" => response(307, &[("location", "/done")], b""),
+            "/loop" => response(302, &[("location", "/loop")], b""),
+            _ => response(200, &[], request.method.as_bytes())
+        }).await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn see_other_changes_the_method_to_get() {
+        let server = redirecting_server().await;
+        let response = fetch_with(&server.url("/see-other"), RequestInit {
+            method: Method::POST,
+            body: Some("form".into()),
+            ..Default::default()
+        }).await.unwrap();
+        assert!(response.redirected());
+        assert_eq!(response.url(), server.url("/done"));
+        assert_eq!(response.text().await.unwrap(), "GET");
+        let received = server.received();
+        assert_eq!(received[1].header("content-type"), None);
+        assert!(received[1].body.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn temporary_redirect_resends_the_body() {
+        let server = redirecting_server().await;
+        let response = fetch_with(&server.url("/temporary"), RequestInit {
+            method: Method::PUT,
+            headers: Headers::from([("authorization", "Bearer token")]),
+            body: Some("data".into()),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "PUT");
+        let received = server.received();
+        assert_eq!(received[1].body, b"data");
+        assert_eq!(received[1].header("authorization"), Some("Bearer token"));
+        let streamed = fetch_with(&server.url("/temporary"), RequestInit {
+            method: Method::PUT,
+            body: Some(ReadableStream::from_async_read(&b"data"[..]).into()),
+            ..Default::default()
+        }).await;
+        assert!(streamed.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn manual_mode_returns_the_redirect() {
+        let server = redirecting_server().await;
+        let response = fetch_with(&server.url("/see-other"), RequestInit {
+            redirect: RequestRedirect::Manual,
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(response.status(), 303);
+        assert!(!response.redirected());
+        assert_eq!(response.headers().get("location").as_deref(), Some("/done"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn too_many_redirects() {
+        let server = redirecting_server().await;
+        let result = fetch_with(&server.url("/loop"), RequestInit {
+            max_redirects: Some(3),
+            ..Default::default()
+        }).await;
+        assert!(result.is_err());
+        assert_eq!(server.received().len(), 4);
+    }
+}
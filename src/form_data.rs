@@ -0,0 +1,270 @@
+//! The javascript-like `FormData`, `Blob` and `File` sent as multipart/form-data bodies.
+
+use crate::{entries, Body};
+use bytes::Bytes;
+
+/// A list of named form fields, mirroring the javascript `FormData`.
+///
+/// A field holds either a string or a file. Used as a request body, the form is encoded as
+/// `multipart/form-data` with a generated boundary.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{fetch_with, File, FormData, Method, RequestInit};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let mut form = FormData::new();
+/// form.append("name", "report");
+/// form.append("upload", File::new("a,b\n1,2", "report.csv", "text/csv"));
+/// let response = fetch_with("https://www.google.com/", RequestInit {
+///     method: Method::POST,
+///     body: Some(form.into()),
+///     ..Default::default()
+/// }).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FormData {
+    entries: Vec<(String, FormDataEntryValue)>
+}
+
+/// The value of a `FormData` field, either a string or a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormDataEntryValue {
+    /// A string value.
+    String(String),
+    /// A file value.
+    File(File)
+}
+
+/// Immutable raw data with a mime type, mirroring the javascript `Blob`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Blob {
+    bytes: Bytes,
+    mime_type: String
+}
+
+/// A `Blob` with a file name, mirroring the javascript `File`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct File {
+    blob: Blob,
+    name: String
+}
+
+impl FormData {
+    /// Creates an empty form.
+    pub fn new() -> FormData {
+        FormData::default()
+    }
+
+    /// Appends a field, keeping any fields which already have the same name.
+    pub fn append(&mut self, name: &str, value: impl Into<FormDataEntryValue>) {
+        self.entries.push((name.to_string(), value.into()));
+    }
+
+    /// Sets a field, replacing every field which already has the same name.
+    pub fn set(&mut self, name: &str, value: impl Into<FormDataEntryValue>) {
+        entries::set(&mut self.entries, name, value.into());
+    }
+
+    /// Gets the value of the first field named `name`.
+    pub fn get(&self, name: &str) -> Option<&FormDataEntryValue> {
+        self.get_all(name).into_iter().next()
+    }
+
+    /// Gets the values of every field named `name`, in order.
+    pub fn get_all(&self, name: &str) -> Vec<&FormDataEntryValue> {
+        self.entries.iter().filter(|(key, _)| key == name).map(|(_, value)| value).collect()
+    }
+
+    /// Whether a field named `name` is present.
+    pub fn has(&self, name: &str) -> bool {
+        self.entries.iter().any(|(key, _)| key == name)
+    }
+
+    /// Removes every field named `name`.
+    pub fn delete(&mut self, name: &str) {
+        self.entries.retain(|(key, _)| key != name);
+    }
+
+    /// Iterates the fields in order as name and value pairs.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &FormDataEntryValue)> {
+        self.entries.iter().map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Encodes the form as `multipart/form-data`, returning the body and its boundary.
+    pub(crate) fn encode(&self) -> (Bytes, String) {
+        let boundary = format!("----js_libFormBoundary{:016x}", fastrand::u64(..));
+        let mut body = Vec::new();
+        for (name, value) in &self.entries {
+            body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+            let name = escape(&normalize_newlines(name));
+            match value {
+                FormDataEntryValue::String(text) => {
+                    body.extend_from_slice(
+                        format!("content-disposition: form-data; name=\"{}\"\r\n\r\n", name).as_bytes()
+                    );
+                    body.extend_from_slice(normalize_newlines(text).as_bytes());
+                },
+                FormDataEntryValue::File(file) => {
+                    let mime_type = match file.mime_type() {
+                        "" => "application/octet-stream",
+                        mime_type => mime_type
+                    };
+                    body.extend_from_slice(format!(
+                        "content-disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\
+                         content-type: {}\r\n\r\n",
+                        name, escape(file.name()), mime_type
+                    ).as_bytes());
+                    body.extend_from_slice(file.bytes());
+                }
+            }
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+        (Bytes::from(body), boundary)
+    }
+}
+
+impl Blob {
+    /// Creates a blob from bytes and a mime type, e.g. `"image/png"`.
+    pub fn new(bytes: impl Into<Bytes>, mime_type: &str) -> Blob {
+        Blob { bytes: bytes.into(), mime_type: mime_type.to_ascii_lowercase() }
+    }
+
+    /// The size of the blob in bytes.
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// The mime type of the blob, or an empty string when it is unknown.
+    pub fn mime_type(&self) -> &str {
+        &self.mime_type
+    }
+
+    /// The contents of the blob.
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+}
+
+impl File {
+    /// Creates a file from bytes, a file name and a mime type, e.g. `"text/csv"`.
+    pub fn new(bytes: impl Into<Bytes>, name: &str, mime_type: &str) -> File {
+        File { blob: Blob::new(bytes, mime_type), name: name.to_string() }
+    }
+
+    /// The name of the file.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The size of the file in bytes.
+    pub fn size(&self) -> usize {
+        self.blob.size()
+    }
+
+    /// The mime type of the file, or an empty string when it is unknown.
+    pub fn mime_type(&self) -> &str {
+        self.blob.mime_type()
+    }
+
+    /// The contents of the file.
+    pub fn bytes(&self) -> &Bytes {
+        self.blob.bytes()
+    }
+}
+
+/// Escapes a name or file name for a `content-disposition` header.
+fn escape(name: &str) -> String {
+    name.replace('\n', "%0A").replace('\r', "%0D").replace('"', "%22")
+}
+
+/// Converts every line break to `\r\n`.
+fn normalize_newlines(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n").replace('\n', "\r\n")
+}
+
+impl From<String> for FormDataEntryValue {
+    fn from(text: String) -> Self {
+        FormDataEntryValue::String(text)
+    }
+}
+
+impl From<&str> for FormDataEntryValue {
+    fn from(text: &str) -> Self {
+        FormDataEntryValue::String(text.to_string())
+    }
+}
+
+impl From<File> for FormDataEntryValue {
+    fn from(file: File) -> Self {
+        FormDataEntryValue::File(file)
+    }
+}
+
+impl From<Blob> for FormDataEntryValue {
+    fn from(blob: Blob) -> Self {
+        FormDataEntryValue::File(File { blob, name: "blob".to_string() })
+    }
+}
+
+impl From<FormData> for Body {
+    fn from(form: FormData) -> Self {
+        let (bytes, boundary) = form.encode();
+        Body::with_content_type(bytes, format!("multipart/form-data; boundary={}", boundary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{response, TestServer};
+    use crate::{fetch_with, Method, RequestInit};
+
+    #[test]
+    fn fields_keep_their_order() {
+        let mut form = FormData::new();
+        form.append("a", "1");
+        form.append("b", Blob::new("data", "Text/Plain"));
+        form.append("a", "2");
+        assert_eq!(form.get("a"), Some(&FormDataEntryValue::from("1")));
+        assert_eq!(form.get_all("a").len(), 2);
+        match form.get("b") {
+            Some(FormDataEntryValue::File(file)) => {
+                assert_eq!((file.name(), file.mime_type(), file.size()), ("blob", "text/plain", 4));
+            },
+            other => panic!("expected a file, got {:?}", other)
+        }
+        form.set("a", "3");
+        let names = form.entries().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b"]);
+        form.delete("b");
+        assert!(!form.has("b"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn post_multipart_form_data() {
+        let server = TestServer::start(|_| response(200, &[], b"")).await;
+        let mut form = FormData::new();
+        form.append("title", "line\nbreak");
+        form.append("upload", File::new("a,b", "my \"report\".csv", "text/csv"));
+        fetch_with(&server.url("/upload"), RequestInit {
+            method: Method::POST,
+            body: Some(form.into()),
+            ..Default::default()
+        }).await.unwrap();
+        let received = &server.received()[0];
+        let content_type = received.header("content-type").unwrap();
+        let boundary = content_type.strip_prefix("multipart/form-data; boundary=").unwrap();
+        let expected = format!(
+            "--{b}\r\ncontent-disposition: form-data; name=\"title\"\r\n\r\nline\r\nbreak\r\n\
+             --{b}\r\ncontent-disposition: form-data; name=\"upload\"; \
+             filename=\"my %22report%22.csv\"\r\n\
+             content-type: text/csv\r\n\r\na,b\r\n--{b}--\r\n",
+            b = boundary
+        );
+        assert_eq!(String::from_utf8(received.body.clone()).unwrap(), expected);
+    }
+}
@@ -0,0 +1,247 @@
+//! The javascript-like `Headers` of requests and responses.
+
+use crate::{entries, Error, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+/// A case-insensitive multimap of http headers, mirroring the javascript `Headers`.
+///
+/// Names are compared case-insensitively and stored lowercased. A name may have several
+/// values, which `get` joins with `", "` the way browsers do. Like in javascript, invalid names
+/// and values are refused with a `TypeError`.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::Headers;
+/// # fn example() -> Result<(), js_lib::Error> {
+/// let mut headers = Headers::from([("Accept", "text/html")]);
+/// headers.append("accept", "application/json")?;
+/// assert_eq!(headers.get("ACCEPT").as_deref(), Some("text/html, application/json"));
+/// assert!(headers.set("accept", "text/html\r\nx-injected: 1").is_err());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    list: Vec<(String, String)>
+}
+
+impl Headers {
+    /// Creates an empty set of headers.
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    /// Appends a value to the header `name`, keeping any values it already has.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - `name` is not a valid header name
+    /// - `value` is not a valid header value
+    pub fn append(&mut self, name: &str, value: &str) -> Result<()> {
+        let entry = validate(name, value)?;
+        self.list.push(entry);
+        Ok(())
+    }
+
+    /// Sets the header `name` to a single value, replacing any values it already has.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - `name` is not a valid header name
+    /// - `value` is not a valid header value
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let (name, value) = validate(name, value)?;
+        entries::set(&mut self.list, &name, value);
+        Ok(())
+    }
+
+    /// Gets the values of the header `name` joined by `", "`, or `None` if it is not present.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let mut values = self.values_of(name).peekable();
+        values.peek()?;
+        Some(values.collect::<Vec<_>>().join(", "))
+    }
+
+    /// Gets every `set-cookie` header value separately, as they can not be joined by commas.
+    pub fn get_set_cookie(&self) -> Vec<String> {
+        self.values_of("set-cookie").map(str::to_string).collect()
+    }
+
+    /// Whether the header `name` is present.
+    pub fn has(&self, name: &str) -> bool {
+        self.values_of(name).next().is_some()
+    }
+
+    /// Removes every value of the header `name`.
+    pub fn delete(&mut self, name: &str) {
+        self.list.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    /// Iterates the headers sorted by name, with the values of each name joined by `", "`.
+    ///
+    /// Like in javascript, every `set-cookie` value is kept as a separate entry.
+    pub fn iter(&self) -> std::vec::IntoIter<(String, String)> {
+        let mut names = self.list.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        names.sort_unstable();
+        names.dedup();
+        let mut entries = Vec::with_capacity(names.len());
+        for name in names {
+            if name == "set-cookie" {
+                for value in self.values_of(name) {
+                    entries.push((name.to_string(), value.to_string()));
+                }
+            } else if let Some(value) = self.get(name) {
+                entries.push((name.to_string(), value));
+            }
+        }
+        entries.into_iter()
+    }
+
+    /// The headers as they were added, one entry per value.
+    pub(crate) fn raw(&self) -> &[(String, String)] {
+        &self.list
+    }
+
+    /// The headers of entries taken from `raw`, which are already valid.
+    pub(crate) fn from_raw(list: Vec<(String, String)>) -> Headers {
+        Headers { list }
+    }
+
+    pub(crate) fn from_header_map(map: &HeaderMap) -> Headers {
+        let list = map.iter()
+            .map(|(name, value)| (name.as_str().to_string(), decode_value(value.as_bytes())))
+            .collect();
+        Headers { list }
+    }
+
+    fn values_of<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.list.iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Decodes a header value from the wire, one character per byte.
+fn decode_value(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| byte as char).collect()
+}
+
+/// Encodes a header value for the wire, byte for byte when every character fits in one.
+pub(crate) fn encode_value(value: &str) -> Vec<u8> {
+    if value.chars().all(|c| (c as u32) <= 0xFF) {
+        value.chars().map(|c| c as u8).collect()
+    } else {
+        value.as_bytes().to_vec()
+    }
+}
+
+/// Strips leading and trailing http whitespace from a header value.
+fn normalize(value: &str) -> String {
+    value.trim_matches(|c| matches!(c, ' ' | '\t' | '\r' | '\n')).to_string()
+}
+
+/// Lowercases `name` and normalizes `value`, failing if either can not be sent.
+fn validate(name: &str, value: &str) -> Result<(String, String)> {
+    let value = normalize(value);
+    match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_bytes(&encode_value(&value))) {
+        (Ok(name), Ok(_)) => Ok((name.as_str().to_string(), value)),
+        (Err(_), _) => Err(Error::type_error(format!("{:?} is not a valid header name", name))),
+        (_, Err(_)) => {
+            Err(Error::type_error(format!("{:?} is not a valid value of the header {}", value, name)))
+        }
+    }
+}
+
+impl IntoIterator for &Headers {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Collects headers from their names and values.
+///
+/// # Panics
+///
+/// This function panics if a name or value is invalid, which `append` returns as an error.
+impl<K, V> FromIterator<(K, V)> for Headers
+    where K: AsRef<str>, V: AsRef<str> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut headers = Headers::new();
+        for (name, value) in iter {
+            if let Err(error) = headers.append(name.as_ref(), value.as_ref()) {
+                panic!("{}", error);
+            }
+        }
+        headers
+    }
+}
+
+/// Creates headers from an array of names and values.
+///
+/// # Panics
+///
+/// This function panics if a name or value is invalid, which `append` returns as an error.
+impl<K, V, const N: usize> From<[(K, V); N]> for Headers
+    where K: AsRef<str>, V: AsRef<str> {
+    fn from(entries: [(K, V); N]) -> Self {
+        entries.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_insensitive_multimap() {
+        let mut headers = Headers::new();
+        headers.append("Accept", " text/html ").unwrap();
+        headers.append("x-id", "1").unwrap();
+        headers.append("ACCEPT", "application/json").unwrap();
+        assert!(headers.has("accept"));
+        assert_eq!(headers.get("Accept").as_deref(), Some("text/html, application/json"));
+        headers.set("accept", "*/*").unwrap();
+        assert_eq!(headers.get("accept").as_deref(), Some("*/*"));
+        assert!(matches!(headers.append("bad name", "1"), Err(Error::TypeError { .. })));
+        assert!(matches!(headers.set("accept", "a\nb"), Err(Error::TypeError { .. })));
+        assert!(matches!(headers.set("x-zero", "\0"), Err(Error::TypeError { .. })));
+        assert_eq!(headers.get("accept").as_deref(), Some("*/*"));
+        assert!(!headers.has("x-zero"));
+        headers.delete("X-Id");
+        assert!(!headers.has("x-id"));
+        assert_eq!(headers.get("x-id"), None);
+    }
+
+    #[test]
+    fn sorted_iteration_keeps_set_cookie_apart() {
+        let headers = Headers::from([
+            ("set-cookie", "a=1"),
+            ("Content-Type", "text/plain"),
+            ("Set-Cookie", "b=2"),
+            ("x-b", "1"),
+            ("x-a", "2"),
+            ("x-b", "3")
+        ]);
+        assert_eq!(headers.get_set_cookie(), ["a=1", "b=2"]);
+        let entries = headers.iter().collect::<Vec<_>>();
+        let expected = [
+            ("content-type", "text/plain"),
+            ("set-cookie", "a=1"),
+            ("set-cookie", "b=2"),
+            ("x-a", "2"),
+            ("x-b", "1, 3")
+        ];
+        assert_eq!(entries.len(), expected.len());
+        for ((name, value), (expected_name, expected_value)) in entries.iter().zip(expected) {
+            assert_eq!((name.as_str(), value.as_str()), (expected_name, expected_value));
+        }
+    }
+}
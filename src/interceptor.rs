@@ -0,0 +1,187 @@
+//! The `Interceptor` middleware chain wrapped around each request a `FetchClient` sends.
+
+use crate::transport::Transport;
+use crate::{Request, Response, Result};
+use futures_util::future::BoxFuture;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+
+/// Middleware around the requests of a `FetchClient`, like javascript's axios interceptors.
+///
+/// An interceptor receives each request with the `Next` step of the chain. It can rewrite the
+/// request before passing it on with `next.run(request)`, inspect or replace the response it
+/// resolves to, or return a response of its own without calling `next` at all. Interceptors run
+/// in the order they were added to the client, the first one seeing the request first and the
+/// response last.
+///
+/// Interceptors see every request sent, including each redirect followed and each retry, but
+/// not responses served by the http cache. Any async closure taking a `Request` and a `Next` is
+/// an interceptor.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{FetchClient, Next, Request};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let client = FetchClient::builder()
+///     .interceptor(|mut request: Request, next: Next| async move {
+///         request.headers_mut().set("authorization", "Bearer token")?;
+///         next.run(request).await
+///     })
+///     .build()?;
+/// let response = client.fetch("https://www.google.com/").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait Interceptor: Send + Sync {
+    /// Handles `request`, usually by passing it on with `next.run(request)`.
+    fn intercept(&self, request: Request, next: Next) -> BoxFuture<'static, Result<Response>>;
+}
+
+impl<F, Fut> Interceptor for F
+    where F: Fn(Request, Next) -> Fut + Send + Sync,
+          Fut: Future<Output = Result<Response>> + Send + 'static {
+    fn intercept(&self, request: Request, next: Next) -> BoxFuture<'static, Result<Response>> {
+        Box::pin(self(request, next))
+    }
+}
+
+/// The rest of an interceptor chain, ending with the transport which sends the request.
+pub struct Next {
+    interceptors: Interceptors,
+    index: usize,
+    transport: Arc<dyn Transport>
+}
+
+impl Next {
+    /// Passes `request` to the next interceptor, or sends it once every interceptor has run.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - a later interceptor fails
+    /// - the request fails to be sent
+    pub async fn run(self, request: Request) -> Result<Response> {
+        match self.interceptors.list.get(self.index).cloned() {
+            Some(interceptor) => {
+                let next = Next { index: self.index + 1, ..self };
+                interceptor.intercept(request, next).await
+            },
+            None => self.transport.send(request).await
+        }
+    }
+}
+
+impl fmt::Debug for Next {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Next")
+            .field("interceptors", &self.interceptors)
+            .field("index", &self.index)
+            .field("transport", &self.transport)
+            .finish()
+    }
+}
+
+/// The interceptors of a client, in the order they were added.
+#[derive(Clone, Default)]
+pub(crate) struct Interceptors {
+    list: Arc<Vec<Arc<dyn Interceptor>>>
+}
+
+impl Interceptors {
+    pub(crate) fn push(&mut self, interceptor: impl Interceptor + 'static) {
+        Arc::make_mut(&mut self.list).push(Arc::new(interceptor));
+    }
+
+    /// Wraps `transport` so every request it sends runs through the interceptors first.
+    pub(crate) fn wrap(&self, transport: Arc<dyn Transport>) -> Arc<dyn Transport> {
+        if self.list.is_empty() {
+            return transport;
+        }
+        Arc::new(Chain { interceptors: self.clone(), transport })
+    }
+}
+
+impl fmt::Debug for Interceptors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Interceptors({})", self.list.len())
+    }
+}
+
+/// A transport running the interceptors before its inner transport.
+#[derive(Debug)]
+struct Chain {
+    interceptors: Interceptors,
+    transport: Arc<dyn Transport>
+}
+
+impl Transport for Chain {
+    fn send(&self, request: Request) -> BoxFuture<'static, Result<Response>> {
+        let interceptors = self.interceptors.clone();
+        let next = Next { interceptors, index: 0, transport: self.transport.clone() };
+        Box::pin(next.run(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, FetchClient, Method, MockFetch, ResponseInit};
+    use std::sync::Mutex;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn interceptors_run_in_registration_order() {
+        let mock = MockFetch::new();
+        mock.on(Method::GET, "https://example.com/*").respond(200, "from the network");
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let (first, second) = (log.clone(), log.clone());
+        let client = FetchClient::builder()
+            .transport(mock.clone())
+            .interceptor(move |mut request: Request, next: Next| {
+                let log = first.clone();
+                async move {
+                    log.lock().unwrap().push("first");
+                    request.headers_mut().set("x-trace-id", "42").unwrap();
+                    let response = next.run(request).await;
+                    log.lock().unwrap().push("first done");
+                    response
+                }
+            })
+            .interceptor(move |request: Request, next: Next| {
+                let log = second.clone();
+                async move {
+                    log.lock().unwrap().push("second");
+                    let response = next.run(request).await?;
+                    Ok(Response::new(response.text().await?.to_uppercase(), ResponseInit::default()))
+                }
+            })
+            .build()
+            .unwrap();
+        let response = client.fetch("https://example.com/").await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "FROM THE NETWORK");
+        assert_eq!(*log.lock().unwrap(), ["first", "second", "first done"]);
+        assert_eq!(mock.calls()[0].headers().get("x-trace-id").as_deref(), Some("42"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn interceptors_can_short_circuit() {
+        let mock = MockFetch::new();
+        let client = FetchClient::builder()
+            .transport(mock.clone())
+            .interceptor(|request: Request, next: Next| async move {
+                if request.url().ends_with("/offline") {
+                    return Ok(Response::new("synthetic", ResponseInit { status: 203, ..Default::default() }));
+                }
+                next.run(request).await
+            })
+            .build()
+            .unwrap();
+        let response = client.fetch("https://example.com/offline").await.unwrap();
+        assert_eq!(response.status(), 203);
+        assert_eq!(response.url(), "https://example.com/offline");
+        assert!(matches!(client.fetch("https://example.com/online").await, Err(Error::TypeError { .. })));
+        assert_eq!(mock.calls().len(), 1);
+    }
+}
@@ -11,35 +11,105 @@
 //! ```rust
 //! use js_lib::fetch;
 //! # async fn example() -> Result<(), js_lib::Error> {
-//! let result = fetch("https://www.google.com/").await;
+//! let text = fetch("https://www.google.com/").await?.text().await?;
 //! # Ok(())
 //! # }
 //! ```
+//!
+//! ## Making a http post request
+//!
+//! ```rust
+//! use js_lib::{fetch_with, Method, RequestInit};
+//! # async fn example() -> Result<(), js_lib::Error> {
+//! let result = fetch_with("https://www.google.com/", RequestInit {
+//!     method: Method::POST,
+//!     body: Some("hello".into()),
+//!     ..Default::default()
+//! }).await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ## Making a blocking http request
+//!
+//! ```rust
+//! use js_lib::blocking;
+//! # fn example() -> Result<(), js_lib::Error> {
+//! let text = blocking::fetch("https://www.google.com/")?.text()?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod blocking;
+
+mod abort;
+mod batch;
+mod cache;
+mod client;
+mod cookie;
+mod date;
+mod entries;
+mod error;
+mod event_source;
+mod fetch;
+mod form_data;
+mod headers;
+mod interceptor;
+mod mock;
+mod progress;
+mod proxy;
+mod request;
+mod response;
+mod retry;
+mod scheme;
+mod stream;
+mod tls;
+mod transport;
+mod url_search_params;
+mod web_socket;
+#[cfg(test)]
+mod test_server;
+
+pub use abort::{AbortController, AbortSignal};
+pub use batch::{FetchAllOptions, RateLimit};
+pub use cache::{HttpCache, RequestCache};
+pub use client::{FetchClient, FetchClientBuilder};
+pub use cookie::{Cookie, CookieJar, RequestCredentials};
+pub use error::{Error, ErrorContext};
+pub use event_source::{EventSource, EventSourceState, MessageEvent};
+pub use form_data::{Blob, File, FormData, FormDataEntryValue};
+pub use headers::Headers;
+pub use interceptor::{Interceptor, Next};
+pub use mock::{MockFetch, MockRoute};
+pub use progress::{Progress, ProgressListener};
+pub use proxy::Proxy;
+pub use request::{Body, Method, Request, RequestInit, RequestRedirect};
+pub use response::{Response, ResponseInit, ValidateStatus};
+pub use retry::{RetryAttempt, RetryPolicy};
+pub use stream::{ReadableStream, ReadableStreamDefaultReader};
+pub use tls::{TlsOptions, TlsVersion};
+pub use transport::Transport;
+pub use url_search_params::URLSearchParams;
+pub use web_socket::{CloseEvent, WebSocket, WebSocketEvent, WebSocketInit, WebSocketMessage};
+pub use web_socket::WebSocketState;
 
 /// A `Result` alias where the `Err` case is `js_lib::Error`.
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// The Errors that may occur.
-///
-/// Note: This is an enumumation of dependency crate errors
-#[derive(Debug)]
-pub enum Error {
-    /// All networking errors which can occur.
-    Network(reqwest::Error),
-    /// All json parsing errors which can occur.
-    ParseJson(serde_json::Error)
-}
-
 /// Fetches data from a url.
 /// 
-/// **NOTE**: This function makes a http GET request.
+/// **NOTE**: This function makes a http GET request, use `fetch_with` for any other request.
+/// Requests are sent by a global `FetchClient`, so connections are reused between calls.
+/// `data:` urls are decoded without any request, while `file:` urls are only read from disk by a
+/// client built with `FetchClientBuilder::allow_file_urls`.
 ///
 /// # Examples
 ///
 /// ```rust
 /// use js_lib::fetch;
 /// # async fn example() -> Result<(), js_lib::Error> {
-/// let result = fetch("https://www.google.com/").await;
+/// let response = fetch("https://www.google.com/").await?;
+/// let text = response.text().await?;
 /// # Ok(())
 /// # }
 /// ```
@@ -49,15 +119,106 @@ pub enum Error {
 /// This function fails if:
 ///
 /// - there is a http request error
-/// - the response has no body
-pub async fn fetch(url: &str) -> Result<String> {
-    match reqwest::get(url).await {
-        Ok(response) => match response.text().await {
-            Ok(data) => Ok(data),
-            Err(error) => Err(Error::Network(error))
-        },
-        Err(error) => Err(Error::Network(error))
-    }
+pub async fn fetch(url: &str) -> Result<Response> {
+    fetch_with(url, RequestInit::default()).await
+}
+
+/// Fetches data from a url, with the method, headers, body and other options of `init`.
+///
+/// **NOTE**: Requests are sent by a global `FetchClient`, so connections are reused between calls.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{fetch_with, Headers, Method, RequestInit};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let result = fetch_with("https://www.google.com/", RequestInit {
+///     method: Method::PUT,
+///     headers: Headers::from([("content-type", "application/json")]),
+///     body: Some(r#"{"name":"js_lib"}"#.into()),
+///     ..Default::default()
+/// }).await;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// This function fails if:
+///
+/// - `url` is not a valid absolute url
+/// - there is a http request error
+/// - a header name or value is invalid
+/// - a streamed body fails, with the error of its source
+/// - a `file:` url is fetched, which the global client does not allow
+/// - a `data:` url is invalid
+/// - a redirect is received while `init.redirect` is `RequestRedirect::Error`
+/// - a redirect is to a url which is not `http:` or `https:`
+/// - more than `init.max_redirects` redirects are followed
+/// - a redirect which resends the body is received for a streamed body
+/// - `init.cache` is `RequestCache::OnlyIfCached` and no response is cached
+/// - `init.retry` retried the request and its last attempt failed, with the earlier attempts
+///   in `Error::attempts`
+/// - the response status is rejected by `init.validate_status`
+/// - `init.tls` is invalid
+/// - `init.signal` is aborted before the response is received
+/// - the global client can not be created, as the TLS backend can not be initialized
+pub async fn fetch_with(url: &str, init: RequestInit) -> Result<Response> {
+    client::global()?.fetch_with(url, init).await
+}
+
+/// Fetches many requests within the concurrency and rate limits of `options`, returning their
+/// results in the order of the requests.
+///
+/// **NOTE**: Requests are sent by a global `FetchClient`, so connections are reused between calls.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{fetch_all, FetchAllOptions, RequestInit};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let urls = (1..=100).map(|page| format!("https://api.example.com/items?page={}", page));
+/// let responses = fetch_all(urls.map(|url| (url, RequestInit::default())), FetchAllOptions {
+///     per_host: Some(4),
+///     ..Default::default()
+/// }).await;
+/// for response in responses {
+///     let text = response?.text().await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn fetch_all<I, U>(requests: I, options: FetchAllOptions) -> Vec<Result<Response>>
+    where I: IntoIterator<Item = (U, RequestInit)>, U: Into<String> {
+    batch::in_order(batch::fetch_all(None, requests, options)).await
+}
+
+/// Fetches many requests like `fetch_all`, streaming each result with the index of its request
+/// as soon as it is received.
+///
+/// # Examples
+///
+/// ```rust
+/// use futures_util::StreamExt;
+/// use js_lib::{fetch_all_stream, FetchAllOptions, RateLimit, RequestInit};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let urls = ["https://www.google.com/", "https://www.rust-lang.org/"];
+/// let mut results = fetch_all_stream(urls.map(|url| (url, RequestInit::default())), FetchAllOptions {
+///     rate_limit: Some(RateLimit::per_second(5)),
+///     ..Default::default()
+/// });
+/// while let Some((index, result)) = results.next().await {
+///     println!("{}: {}", urls[index], result?.status());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn fetch_all_stream<I, U>(
+    requests: I,
+    options: FetchAllOptions
+) -> impl futures_util::Stream<Item = (usize, Result<Response>)> + Send + Unpin + 'static
+    where I: IntoIterator<Item = (U, RequestInit)>, U: Into<String> {
+    batch::fetch_all(None, requests, options)
 }
 
 /// Deserializes a json string slice into type T.
@@ -81,20 +242,24 @@ pub fn from_json<T>(json: &str) -> Result<T>
     where T: serde::de::DeserializeOwned {
     match serde_json::from_str::<T>(json) {
         Ok(data_struct) => Ok(data_struct),
-        Err(error) => Err(Error::ParseJson(error))
+        Err(error) => Err(Error::from(error))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use test_server::{response, TestServer};
 
     static TEST_API: &str = "https://random-word-api.herokuapp.com/word";
 
     #[tokio::test(flavor = "multi_thread")]
     async fn fetch_api() {
-        let result = fetch(TEST_API).await;
+        let mock = MockFetch::new();
+        mock.on(Method::GET, TEST_API).respond_json(200, &["word"]);
+        let result = mock.scope(fetch(TEST_API)).await;
         assert!(result.is_ok());
+        assert_eq!(result.unwrap().json::<Vec<String>>().await.unwrap(), ["word"]);
     }
 
     #[tokio::test(flavor = "multi_thread")]
@@ -102,4 +267,67 @@ mod tests {
         let result = from_json::<Vec<String>>(r#"["1","2","3"]"#);
         assert!(result.is_ok());
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fetch_with_method_headers_and_body() {
+        let server = TestServer::start(|_| response(200, &[], b"created")).await;
+        let result = fetch_with(&server.url("/items"), RequestInit {
+            method: Method::POST,
+            headers: Headers::from([("x-token", "secret")]),
+            body: Some("hello".into()),
+            ..Default::default()
+        }).await;
+        assert_eq!(result.unwrap().text().await.unwrap(), "created");
+        let received = &server.received()[0];
+        assert_eq!(received.method, "POST");
+        assert_eq!(received.target, "/items");
+        assert_eq!(received.header("x-token"), Some("secret"));
+        assert_eq!(received.header("content-type"), Some("text/plain;charset=UTF-8"));
+        assert_eq!(received.body, b"hello");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fetch_with_redirect_error() {
+        let server = TestServer::start(|request| match request.target.as_str() {
+            "/old" => response(301, &[("location", "/new")], b""),
+            _ => response(200, &[], b"moved")
+        }).await;
+        let followed = fetch_with(&server.url("/old"), RequestInit::default()).await;
+        assert_eq!(followed.unwrap().text().await.unwrap(), "moved");
+        let result = fetch_with(&server.url("/old"), RequestInit {
+            redirect: RequestRedirect::Error,
+            ..Default::default()
+        }).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fetch_with_streamed_body() {
+        let server = TestServer::start(|_| response(200, &[], b"")).await;
+        let chunks = vec![Ok(bytes::Bytes::from("large ")), Ok(bytes::Bytes::from("upload"))];
+        let result = fetch_with(&server.url("/upload"), RequestInit {
+            method: Method::PUT,
+            body: Some(ReadableStream::new(futures_util::stream::iter(chunks)).into()),
+            ..Default::default()
+        }).await;
+        assert!(result.unwrap().ok());
+        let received = &server.received()[0];
+        assert_eq!(received.header("transfer-encoding"), Some("chunked"));
+        assert_eq!(received.body, b"large upload");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fetch_with_failing_streamed_body() {
+        let server = TestServer::start(|_| response(200, &[], b"")).await;
+        let chunks = vec![Ok(bytes::Bytes::from("partial")), Err(Error::type_error("disk failed"))];
+        let result = fetch_with(&server.url("/upload"), RequestInit {
+            method: Method::POST,
+            body: Some(ReadableStream::new(futures_util::stream::iter(chunks)).into()),
+            ..Default::default()
+        }).await;
+        match result {
+            Err(Error::TypeError { message, .. }) => assert_eq!(message, "disk failed"),
+            other => panic!("expected the stream's error, got {:?}", other)
+        }
+    }
 }
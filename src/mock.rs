@@ -0,0 +1,267 @@
+//! The `MockFetch` transport which answers requests with canned responses in tests.
+
+use crate::transport::{self, Transport};
+use crate::{Body, Error, Headers, Method, Request, Response, ResponseInit, Result};
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+/// A `Transport` answering requests with canned responses instead of the network, and
+/// recording every request it receives.
+///
+/// Routes are matched by method, url pattern, headers and body, in the order they were added,
+/// and a request no route matches fails with `Error::TypeError`. In a url pattern, `*` matches any
+/// run of characters. Clones of a mock share the same routes and calls.
+///
+/// A mock is used either by a `FetchClient` built with it as its transport, or by every
+/// `fetch` made within `MockFetch::scope`, including those of the free `fetch` functions.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{fetch, Method, MockFetch};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let mock = MockFetch::new();
+/// mock.on(Method::GET, "https://api.example.com/users/*").respond(200, r#"{"name":"js_lib"}"#);
+/// let text = mock.scope(async {
+///     fetch("https://api.example.com/users/1").await?.text().await
+/// }).await?;
+/// assert_eq!(mock.calls()[0].url(), "https://api.example.com/users/1");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MockFetch {
+    inner: Arc<Inner>
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    routes: Mutex<Vec<MockRoute>>,
+    calls: Mutex<Vec<Request>>
+}
+
+/// A route of a `MockFetch`, added once it is given a response with `respond` or
+/// `respond_with`.
+pub struct MockRoute {
+    mock: MockFetch,
+    method: Method,
+    pattern: String,
+    headers: Headers,
+    body: Option<Bytes>,
+    handler: Option<Arc<Handler>>
+}
+
+type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+impl MockFetch {
+    /// Creates a mock without any routes.
+    pub fn new() -> MockFetch {
+        MockFetch::default()
+    }
+
+    /// Starts a route matching requests with `method` whose url matches `pattern`.
+    pub fn on(&self, method: Method, pattern: &str) -> MockRoute {
+        MockRoute {
+            mock: self.clone(),
+            method,
+            pattern: pattern.to_string(),
+            headers: Headers::new(),
+            body: None,
+            handler: None
+        }
+    }
+
+    /// Every request received so far, in order.
+    pub fn calls(&self) -> Vec<Request> {
+        let calls = self.inner.calls.lock().unwrap();
+        calls.iter().filter_map(Request::try_clone).collect()
+    }
+
+    /// Runs `future`, sending every `fetch` it makes to this mock, whichever client makes it.
+    ///
+    /// **NOTE**: Only the task running `future` is mocked, not tasks it spawns, nor the
+    /// `blocking` module.
+    pub async fn scope<F: Future>(&self, future: F) -> F::Output {
+        transport::scope(Arc::new(self.clone()), future).await
+    }
+}
+
+impl Transport for MockFetch {
+    fn send(&self, mut request: Request) -> BoxFuture<'static, Result<Response>> {
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            // A streamed body is read completely, so it can be matched and recorded.
+            if let Some(body) = request.take_body() {
+                let bytes = match body.as_bytes() {
+                    Some(bytes) => bytes.clone(),
+                    None => Bytes::from(body.into_stream().read_all().await?)
+                };
+                request.set_body(Some(bytes.into()));
+            }
+            let handler = {
+                let routes = inner.routes.lock().unwrap();
+                let route = routes.iter().find(|route| route.matches(&request));
+                route.and_then(|route| route.handler.clone())
+            };
+            let response = handler.map(|handler| handler(&request));
+            let description = format!("{} {}", request.method(), request.url());
+            inner.calls.lock().unwrap().push(request);
+            match response {
+                Some(response) => Ok(response),
+                None => Err(Error::type_error(format!("no mock route matches {}", description)))
+            }
+        })
+    }
+}
+
+impl MockRoute {
+    /// Only matches requests with the header `name` set to `value`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `name` or `value` is not a valid header.
+    pub fn header(mut self, name: &str, value: &str) -> MockRoute {
+        if let Err(error) = self.headers.append(name, value) {
+            panic!("{}", error);
+        }
+        self
+    }
+
+    /// Only matches requests with exactly this body.
+    pub fn body(mut self, body: impl Into<Bytes>) -> MockRoute {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Answers matching requests with a status and a body.
+    pub fn respond(self, status: u16, body: impl Into<Bytes>) {
+        let body = body.into();
+        self.respond_with(move |_| {
+            Response::new(body.clone(), ResponseInit { status, ..Default::default() })
+        });
+    }
+
+    /// Answers matching requests with a status and a value serialized as json.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `value` can not be serialized as json.
+    pub fn respond_json<T: serde::Serialize>(self, status: u16, value: &T) {
+        let json = serde_json::to_vec(value).expect("the mock response could not be serialized");
+        let headers = Headers::from([("content-type", "application/json")]);
+        let body = Bytes::from(json);
+        self.respond_with(move |_| {
+            Response::new(body.clone(), ResponseInit { status, headers: headers.clone() })
+        });
+    }
+
+    /// Answers matching requests with the response returned by `handler`.
+    pub fn respond_with<F>(mut self, handler: F)
+        where F: Fn(&Request) -> Response + Send + Sync + 'static {
+        self.handler = Some(Arc::new(handler));
+        let mock = self.mock.clone();
+        mock.inner.routes.lock().unwrap().push(self);
+    }
+
+    fn matches(&self, request: &Request) -> bool {
+        let headers_match = self.headers.raw().iter()
+            .all(|(name, value)| request.headers().get(name).as_deref() == Some(value));
+        let body = request.body().and_then(Body::as_bytes).cloned().unwrap_or_default();
+        *request.method() == self.method
+            && wildcard_match(&self.pattern, request.url())
+            && headers_match
+            && self.body.as_ref().is_none_or(|expected| *expected == body)
+    }
+}
+
+impl fmt::Debug for MockRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockRoute")
+            .field("method", &self.method)
+            .field("pattern", &self.pattern)
+            .field("headers", &self.headers)
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any run of characters.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fetch_with, FetchClient, RequestInit};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn routes_match_method_url_headers_and_body() {
+        let mock = MockFetch::new();
+        mock.on(Method::POST, "https://example.com/login").body("secret").respond(200, "welcome");
+        mock.on(Method::GET, "https://example.com/*/me").header("authorization", "token").respond(200, "me");
+        mock.on(Method::GET, "https://example.com/*").respond_json(404, &["missing"]);
+        let client = FetchClient::builder().transport(mock.clone()).build().unwrap();
+        let login = client.fetch_with("https://example.com/login", RequestInit {
+            method: Method::POST,
+            body: Some("secret".into()),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(login.text().await.unwrap(), "welcome");
+        let me = client.fetch_with("https://example.com/users/me", RequestInit {
+            headers: Headers::from([("authorization", "token")]),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(me.url(), "https://example.com/users/me");
+        assert_eq!(me.text().await.unwrap(), "me");
+        let anonymous = client.fetch("https://example.com/users/me").await.unwrap();
+        assert_eq!(anonymous.status(), 404);
+        assert_eq!(anonymous.json::<Vec<String>>().await.unwrap(), ["missing"]);
+        assert!(client.fetch("https://other.example.com/").await.is_err());
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(calls[0].body().and_then(Body::as_bytes).unwrap(), "secret");
+        assert_eq!(calls[0].headers().get("content-type").as_deref(), Some("text/plain;charset=UTF-8"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn scope_mocks_the_free_functions() {
+        let mock = MockFetch::new();
+        mock.on(Method::GET, "http://mock.test/old").respond_with(|_| Response::new("", ResponseInit {
+            status: 301,
+            headers: Headers::from([("location", "/new")])
+        }));
+        mock.on(Method::GET, "http://mock.test/new").respond(200, "moved");
+        let response = mock.scope(fetch_with("http://mock.test/old", RequestInit::default())).await.unwrap();
+        assert!(response.redirected());
+        assert_eq!(response.text().await.unwrap(), "moved");
+        assert_eq!(mock.calls().len(), 2);
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("https://*.example.com/*", "https://api.example.com/v1"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("a*a", "aa"));
+        assert!(!wildcard_match("a*a", "a"));
+        assert!(!wildcard_match("https://example.com/", "https://example.com/users"));
+    }
+}
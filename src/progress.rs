@@ -0,0 +1,121 @@
+//! The `Progress` of request uploads and response downloads.
+
+use crate::ReadableStream;
+use futures_util::StreamExt;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// How much of a body has been transferred, like the javascript `ProgressEvent`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// The number of bytes transferred so far.
+    pub loaded: u64,
+    /// The size of the body in bytes, or `None` when it is not known in advance.
+    pub total: Option<u64>
+}
+
+/// Receives the `Progress` of a body each time a chunk of it is transferred.
+///
+/// A listener either calls a callback, or publishes to a `tokio::sync::watch` channel so the
+/// latest progress can be read from another task, like a progress bar.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{fetch_with, ProgressListener, RequestInit};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let (listener, mut progress) = ProgressListener::watch();
+/// tokio::spawn(async move {
+///     while progress.changed().await.is_ok() {
+///         let progress = *progress.borrow();
+///         println!("{} of {:?} bytes", progress.loaded, progress.total);
+///     }
+/// });
+/// let response = fetch_with("https://www.google.com/", RequestInit {
+///     download_progress: Some(listener),
+///     ..Default::default()
+/// }).await?;
+/// let bytes = response.bytes().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ProgressListener {
+    callback: Arc<dyn Fn(Progress) + Send + Sync>
+}
+
+impl ProgressListener {
+    /// Creates a listener calling `callback` with each progress.
+    pub fn new(callback: impl Fn(Progress) + Send + Sync + 'static) -> ProgressListener {
+        ProgressListener { callback: Arc::new(callback) }
+    }
+
+    /// Creates a listener publishing each progress to the returned watch channel.
+    pub fn watch() -> (ProgressListener, watch::Receiver<Progress>) {
+        let (sender, receiver) = watch::channel(Progress::default());
+        let listener = ProgressListener::new(move |progress| {
+            sender.send_replace(progress);
+        });
+        (listener, receiver)
+    }
+
+    /// Wraps `stream` to report the progress of every chunk read from it.
+    pub(crate) fn observe(&self, stream: ReadableStream, total: Option<u64>) -> ReadableStream {
+        let callback = self.callback.clone();
+        let mut loaded = 0;
+        ReadableStream::new(stream.inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                loaded += chunk.len() as u64;
+                callback(Progress { loaded, total });
+            }
+        }))
+    }
+}
+
+impl fmt::Debug for ProgressListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressListener").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{response, TestServer};
+    use crate::{fetch_with, Method, RequestInit};
+    use std::sync::Mutex;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn upload_progress_keeps_the_content_length() {
+        let server = TestServer::start(|_| response(200, &[], b"")).await;
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        let listener = ProgressListener::new(move |progress| log.lock().unwrap().push(progress));
+        fetch_with(&server.url("/upload"), RequestInit {
+            method: Method::PUT,
+            body: Some(vec![7u8; 150_000].into()),
+            upload_progress: Some(listener),
+            ..Default::default()
+        }).await.unwrap();
+        let received = &server.received()[0];
+        assert_eq!(received.header("content-length"), Some("150000"));
+        assert_eq!(received.body.len(), 150_000);
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2], Progress { loaded: 150_000, total: Some(150_000) });
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn download_progress_is_watched() {
+        let server = TestServer::start(|_| response(200, &[], &[1u8; 1000])).await;
+        let (listener, progress) = ProgressListener::watch();
+        let response = fetch_with(&server.url("/download"), RequestInit {
+            download_progress: Some(listener),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(*progress.borrow(), Progress { loaded: 0, total: None });
+        assert_eq!(response.bytes().await.unwrap().len(), 1000);
+        assert_eq!(*progress.borrow(), Progress { loaded: 1000, total: Some(1000) });
+    }
+}
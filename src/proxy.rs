@@ -0,0 +1,180 @@
+//! The `Proxy` servers a `FetchClient` sends its requests through.
+
+use crate::{Error, Result};
+use std::fmt;
+
+/// A proxy server requests are sent through, set with `FetchClientBuilder::proxy`.
+///
+/// The scheme of the proxy url is its protocol: `http://` or `https://` for a http proxy,
+/// `socks5://` for a SOCKS5 proxy with host names resolved locally, or `socks5h://` for one which
+/// resolves them itself. A proxy is used for the requests of one scheme, or of every scheme,
+/// except those to the hosts of its `no_proxy` list.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{FetchClient, Proxy};
+/// # fn example() -> Result<(), js_lib::Error> {
+/// let client = FetchClient::builder()
+///     .proxy(Proxy::https("http://proxy.example.com:8080")
+///         .basic_auth("user", "password")
+///         .no_proxy("localhost, .internal.example.com"))
+///     .proxy(Proxy::all("socks5h://127.0.0.1:1080"))
+///     .env_proxy(false)
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct Proxy {
+    url: String,
+    scheme: Scheme,
+    credentials: Option<(String, String)>,
+    no_proxy: Option<String>
+}
+
+/// The scheme of the requests a proxy is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scheme {
+    All,
+    Http,
+    Https
+}
+
+impl Proxy {
+    /// Creates a proxy for every request.
+    pub fn all(url: &str) -> Proxy {
+        Proxy::new(url, Scheme::All)
+    }
+
+    /// Creates a proxy for requests to `http://` urls.
+    pub fn http(url: &str) -> Proxy {
+        Proxy::new(url, Scheme::Http)
+    }
+
+    /// Creates a proxy for requests to `https://` urls.
+    pub fn https(url: &str) -> Proxy {
+        Proxy::new(url, Scheme::Https)
+    }
+
+    fn new(url: &str, scheme: Scheme) -> Proxy {
+        Proxy { url: url.to_string(), scheme, credentials: None, no_proxy: None }
+    }
+
+    /// Sets the username and password the proxy is authenticated with.
+    pub fn basic_auth(mut self, username: &str, password: &str) -> Proxy {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    /// Sets the hosts which are not sent through the proxy, in the format of the `NO_PROXY`
+    /// environment variable.
+    ///
+    /// The list is separated by commas. An entry is an ip address, an ip range like
+    /// `"192.168.1.0/24"`, `"*"` for every host, or a domain, which also matches its subdomains.
+    pub fn no_proxy(mut self, no_proxy: &str) -> Proxy {
+        self.no_proxy = Some(no_proxy.to_string());
+        self
+    }
+
+    /// Sets the hosts which are not sent through the proxy to the `NO_PROXY` environment
+    /// variable, or to none when it is not set.
+    pub fn no_proxy_from_env(mut self) -> Proxy {
+        self.no_proxy = std::env::var("NO_PROXY").or_else(|_| std::env::var("no_proxy")).ok();
+        self
+    }
+
+    pub(crate) fn into_reqwest(self) -> Result<reqwest::Proxy> {
+        let proxy = match self.scheme {
+            Scheme::All => reqwest::Proxy::all(&self.url),
+            Scheme::Http => reqwest::Proxy::http(&self.url),
+            Scheme::Https => reqwest::Proxy::https(&self.url)
+        };
+        let mut proxy = match proxy {
+            Ok(proxy) => proxy,
+            Err(error) => {
+                return Err(Error::type_error(format!("invalid proxy url {:?}: {}", self.url, error)));
+            }
+        };
+        if let Some((username, password)) = &self.credentials {
+            proxy = proxy.basic_auth(username, password);
+        }
+        Ok(proxy.no_proxy(self.no_proxy.as_deref().and_then(reqwest::NoProxy::from_string)))
+    }
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The password is left out, so a logged client does not leak it.
+        f.debug_struct("Proxy")
+            .field("url", &self.url)
+            .field("scheme", &self.scheme)
+            .field("username", &self.credentials.as_ref().map(|(username, _)| username))
+            .field("no_proxy", &self.no_proxy)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{response, TestServer};
+    use crate::FetchClient;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn http_proxies_get_the_absolute_url() {
+        let proxy = TestServer::start(|_| response(200, &[], b"proxied")).await;
+        let server = TestServer::start(|_| response(200, &[], b"direct")).await;
+        let client = FetchClient::builder()
+            .proxy(Proxy::http(&proxy.url("")).basic_auth("user", "secret").no_proxy("127.0.0.1"))
+            .build()
+            .unwrap();
+        let text = client.fetch("http://api.example.com/users").await.unwrap().text().await.unwrap();
+        assert_eq!(text, "proxied");
+        let received = &proxy.received()[0];
+        assert_eq!(received.target, "http://api.example.com/users");
+        assert_eq!(received.header("proxy-authorization"), Some("Basic dXNlcjpzZWNyZXQ="));
+        assert_eq!(client.fetch(&server.url("/")).await.unwrap().text().await.unwrap(), "direct");
+        assert_eq!(proxy.received().len(), 1);
+        let debug = format!("{:?}", Proxy::all("http://proxy").basic_auth("user", "secret"));
+        assert!(!debug.contains("secret"));
+        assert!(FetchClient::builder().proxy(Proxy::all("not a url")).build().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn socks5_proxies_connect_to_the_host() {
+        let server = TestServer::start(|_| response(200, &[], b"tunneled")).await;
+        let port = server.url("").rsplit(':').next().unwrap().parse::<u16>().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_url = format!("socks5h://{}", listener.local_addr().unwrap());
+        let destinations = Arc::new(Mutex::new(Vec::new()));
+        let log = destinations.clone();
+        tokio::spawn(async move {
+            let (mut client, _) = listener.accept().await.unwrap();
+            // The greeting offers the methods, of which "no authentication" is picked.
+            let mut greeting = [0; 2];
+            client.read_exact(&mut greeting).await.unwrap();
+            client.read_exact(&mut vec![0; greeting[1] as usize]).await.unwrap();
+            client.write_all(&[5, 0]).await.unwrap();
+            // A connect request to a domain name, which the proxy resolves to the test server.
+            let mut request = [0; 5];
+            client.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request[..4], [5, 1, 0, 3]);
+            let mut host = vec![0; request[4] as usize + 2];
+            client.read_exact(&mut host).await.unwrap();
+            let target_port = u16::from_be_bytes([host[host.len() - 2], host[host.len() - 1]]);
+            host.truncate(host.len() - 2);
+            log.lock().unwrap().push(format!("{}:{}", String::from_utf8(host).unwrap(), target_port));
+            client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await.unwrap();
+            let mut upstream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+        });
+        let client = FetchClient::builder().proxy(Proxy::all(&proxy_url)).build().unwrap();
+        let text = client.fetch("http://tunnel.example.com:8080/").await.unwrap().text().await.unwrap();
+        assert_eq!(text, "tunneled");
+        assert_eq!(*destinations.lock().unwrap(), ["tunnel.example.com:8080"]);
+    }
+}
@@ -0,0 +1,329 @@
+//! The javascript-like `RequestInit` options passed to `fetch_with`.
+
+use crate::{AbortSignal, CookieJar, Error, Headers, HttpCache, ReadableStream, RequestCache};
+use crate::{ProgressListener, RequestCredentials, RetryPolicy, TlsOptions, ValidateStatus};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use std::sync::{Arc, Mutex};
+
+/// The size of the chunks a body of bytes is sent in when its upload progress is reported.
+const PROGRESS_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The http method of a request, e.g. `Method::POST`.
+pub use reqwest::Method;
+
+/// The options of a http request, mirroring the javascript `RequestInit` dictionary.
+///
+/// Every field has a default, so only the options which differ from a plain GET request need
+/// to be given.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{Headers, Method, RequestInit};
+/// let init = RequestInit {
+///     method: Method::POST,
+///     headers: Headers::from([("content-type", "application/json")]),
+///     body: Some(r#"{"name":"js_lib"}"#.into()),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Default)]
+pub struct RequestInit {
+    /// The http method, `GET` by default.
+    pub method: Method,
+    /// The headers sent with the request.
+    pub headers: Headers,
+    /// The body sent with the request, if any.
+    pub body: Option<Body>,
+    /// How redirect responses are handled, `RequestRedirect::Follow` by default.
+    pub redirect: RequestRedirect,
+    /// The most redirects followed before the request fails, or `None` for the fetch standard's
+    /// limit of 20.
+    pub max_redirects: Option<usize>,
+    /// Which requests send and store cookies, `RequestCredentials::SameOrigin` by default.
+    pub credentials: RequestCredentials,
+    /// The jar cookies are sent from and stored in, or `None` to send and store no cookies.
+    pub cookie_jar: Option<CookieJar>,
+    /// How the request uses the http cache, `RequestCache::Default` by default.
+    pub cache: RequestCache,
+    /// The http cache responses are served from and stored in, or `None` to cache nothing.
+    pub http_cache: Option<HttpCache>,
+    /// When a failed request is retried, or `None` to send it only once.
+    pub retry: Option<RetryPolicy>,
+    /// Which response statuses are successful, the others failing the request with
+    /// `Error::HttpStatus`, or `None` to return a response of any status.
+    pub validate_status: Option<ValidateStatus>,
+    /// The TLS options of the connection, instead of those of the client, or `None` to use the
+    /// client's.
+    ///
+    /// A client keeps a connection pool for each of the last few options it was given, so these
+    /// options should come from a small fixed set to reuse connections.
+    pub tls: Option<TlsOptions>,
+    /// Receives the progress of sending the request body.
+    pub upload_progress: Option<ProgressListener>,
+    /// Receives the progress of reading the response body.
+    pub download_progress: Option<ProgressListener>,
+    /// A signal which aborts the request, including the reading of its response body.
+    pub signal: Option<AbortSignal>
+}
+
+impl RequestInit {
+    /// Clones the options so the request can be sent again, or `None` if its body is streamed.
+    pub(crate) fn try_clone(&self) -> Option<RequestInit> {
+        let body = match &self.body {
+            Some(body) => Some(body.try_clone()?),
+            None => None
+        };
+        Some(RequestInit {
+            method: self.method.clone(),
+            headers: self.headers.clone(),
+            body,
+            redirect: self.redirect,
+            max_redirects: self.max_redirects,
+            credentials: self.credentials,
+            cookie_jar: self.cookie_jar.clone(),
+            cache: self.cache,
+            http_cache: self.http_cache.clone(),
+            retry: self.retry.clone(),
+            validate_status: self.validate_status.clone(),
+            tls: self.tls.clone(),
+            upload_progress: self.upload_progress.clone(),
+            download_progress: self.download_progress.clone(),
+            signal: self.signal.clone()
+        })
+    }
+}
+
+/// How redirect responses are handled, mirroring the javascript `redirect` option.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RequestRedirect {
+    /// Redirects are followed, up to `RequestInit::max_redirects` of them.
+    #[default]
+    Follow,
+    /// A redirect fails the request.
+    Error,
+    /// Redirects are not followed, the redirect response itself is returned so its `location`
+    /// header can be inspected.
+    Manual
+}
+
+/// A http request as it is handed to a `Transport`, after redirects, cookies and the http cache
+/// have been applied.
+#[derive(Debug)]
+pub struct Request {
+    method: Method,
+    url: String,
+    headers: Headers,
+    body: Option<Body>
+}
+
+impl Request {
+    pub(crate) fn new(method: Method, url: String, headers: Headers, body: Option<Body>) -> Request {
+        Request { method, url, headers, body }
+    }
+
+    /// The http method, e.g. `Method::GET`.
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// The absolute url the request is sent to.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The headers sent with the request.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// The headers sent with the request, to be changed by an `Interceptor`.
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    /// The body sent with the request, if any.
+    pub fn body(&self) -> Option<&Body> {
+        self.body.as_ref()
+    }
+
+    /// Takes the body so it can be sent, leaving `None` in its place.
+    pub fn take_body(&mut self) -> Option<Body> {
+        self.body.take()
+    }
+
+    /// Replaces the body sent with the request.
+    pub fn set_body(&mut self, body: Option<Body>) {
+        self.body = body;
+    }
+
+    /// Clones the request, or `None` if its body is streamed.
+    pub(crate) fn try_clone(&self) -> Option<Request> {
+        let body = match &self.body {
+            Some(body) => Some(body.try_clone()?),
+            None => None
+        };
+        Some(Request::new(self.method.clone(), self.url.clone(), self.headers.clone(), body))
+    }
+}
+
+/// The body of a http request.
+///
+/// A body is created from text, bytes, a `FormData`, a `URLSearchParams` or a `ReadableStream`
+/// with `into()`. A streamed body is uploaded as it is read, with chunked transfer encoding, so
+/// it never sits fully in memory.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{Body, ReadableStream};
+/// let text: Body = "hello".into();
+/// let bytes: Body = vec![0u8, 1, 2].into();
+/// let stream: Body = ReadableStream::from_async_read(&b"from any AsyncRead"[..]).into();
+/// ```
+#[derive(Debug, Default)]
+pub struct Body {
+    kind: BodyKind,
+    content_type: Option<String>
+}
+
+#[derive(Debug)]
+enum BodyKind {
+    Bytes(Bytes),
+    Stream(ReadableStream)
+}
+
+impl Default for BodyKind {
+    fn default() -> Self {
+        BodyKind::Bytes(Bytes::new())
+    }
+}
+
+impl Body {
+    /// Creates a body of bytes, sent with `content_type` when the request sets none itself.
+    pub(crate) fn with_content_type(bytes: Bytes, content_type: String) -> Body {
+        Body { kind: BodyKind::Bytes(bytes), content_type: Some(content_type) }
+    }
+
+    /// Clones the body so it can be sent again, or `None` if it is streamed.
+    pub(crate) fn try_clone(&self) -> Option<Body> {
+        match &self.kind {
+            BodyKind::Bytes(bytes) => Some(Body {
+                kind: BodyKind::Bytes(bytes.clone()),
+                content_type: self.content_type.clone()
+            }),
+            BodyKind::Stream(_) => None
+        }
+    }
+
+    /// The bytes of the body, or `None` if it is streamed.
+    pub fn as_bytes(&self) -> Option<&Bytes> {
+        match &self.kind {
+            BodyKind::Bytes(bytes) => Some(bytes),
+            BodyKind::Stream(_) => None
+        }
+    }
+
+    /// Converts the body into a stream of chunks, a single chunk unless it is streamed.
+    pub fn into_stream(self) -> ReadableStream {
+        match self.kind {
+            BodyKind::Bytes(bytes) => ReadableStream::new(futures_util::stream::iter([Ok(bytes)])),
+            BodyKind::Stream(stream) => stream
+        }
+    }
+
+    /// Reports the progress of sending the body to `listener`, returning the body with its size
+    /// when it is known, from `content_length` for a streamed body.
+    ///
+    /// A body of bytes is sent in chunks, so its progress is reported as it is written.
+    pub(crate) fn with_progress(
+        self,
+        listener: &ProgressListener,
+        content_length: Option<u64>
+    ) -> (Body, Option<u64>) {
+        let (stream, total) = match self.kind {
+            BodyKind::Bytes(bytes) => {
+                let total = bytes.len() as u64;
+                let chunks = (0..bytes.len()).step_by(PROGRESS_CHUNK_SIZE).map(move |start| {
+                    Ok(bytes.slice(start..bytes.len().min(start + PROGRESS_CHUNK_SIZE)))
+                });
+                (ReadableStream::new(futures_util::stream::iter(chunks)), Some(total))
+            },
+            BodyKind::Stream(stream) => (stream, content_length)
+        };
+        let stream = listener.observe(stream, total);
+        (Body { kind: BodyKind::Stream(stream), content_type: self.content_type }, total)
+    }
+
+    /// The `content-type` sent with this body when the request sets none itself.
+    pub(crate) fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Converts the body for reqwest, keeping any error of a streamed body in `source_error`.
+    pub(crate) fn into_reqwest(self, source_error: &SourceError) -> reqwest::Body {
+        match self.kind {
+            BodyKind::Bytes(bytes) => reqwest::Body::from(bytes),
+            BodyKind::Stream(stream) => {
+                let source_error = source_error.clone();
+                reqwest::Body::wrap_stream(stream.map(move |chunk| match chunk {
+                    Ok(chunk) => Ok(chunk),
+                    Err(error) => {
+                        source_error.0.lock().unwrap().replace(error);
+                        Err(std::io::Error::other("the request body stream failed"))
+                    }
+                }))
+            }
+        }
+    }
+}
+
+/// The error of a streamed request body, which reqwest only reports as a failed request.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SourceError(Arc<Mutex<Option<Error>>>);
+
+impl SourceError {
+    pub(crate) fn take(&self) -> Option<Error> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body {
+            kind: BodyKind::Bytes(Bytes::from(text)),
+            content_type: Some("text/plain;charset=UTF-8".to_string())
+        }
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Self {
+        Body::from(text.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::from(Bytes::from(bytes))
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Self {
+        Body::from(bytes.to_vec())
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Body { kind: BodyKind::Bytes(bytes), content_type: None }
+    }
+}
+
+impl From<ReadableStream> for Body {
+    fn from(stream: ReadableStream) -> Self {
+        Body { kind: BodyKind::Stream(stream), content_type: None }
+    }
+}
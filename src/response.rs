@@ -0,0 +1,404 @@
+//! The javascript-like `Response` returned by `fetch`.
+
+use crate::{AbortSignal, Body, Error, Headers, ProgressListener, ReadableStream, Result, RetryAttempt};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use std::fmt;
+use std::sync::Arc;
+use tokio::time::Instant;
+
+/// The most bytes of a body read into the `body_snippet` of `Error::HttpStatus`.
+const BODY_SNIPPET_LENGTH: usize = 512;
+
+/// The response to a http request, mirroring the javascript `Response`.
+///
+/// The status, headers and url are available straight away, while the body is read by one of
+/// the consuming methods `text`, `json`, `bytes` or `array_buffer`, or streamed with `body`.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::fetch;
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let response = fetch("https://www.google.com/").await?;
+/// if response.ok() {
+///     let text = response.text().await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Response {
+    status: u16,
+    headers: Headers,
+    url: String,
+    redirected: bool,
+    attempts: Vec<RetryAttempt>,
+    body: Option<ReadableStream>
+}
+
+/// The status and headers of a `Response` created with `Response::new`, mirroring the
+/// javascript `ResponseInit` dictionary.
+#[derive(Debug, Clone)]
+pub struct ResponseInit {
+    /// The http status code, `200` by default.
+    pub status: u16,
+    /// The response headers.
+    pub headers: Headers
+}
+
+impl Default for ResponseInit {
+    fn default() -> Self {
+        ResponseInit { status: 200, headers: Headers::new() }
+    }
+}
+
+/// Decides which response statuses are successful, like the `validateStatus` option of axios.
+///
+/// A response with any other status fails its request with `Error::HttpStatus`, which carries
+/// the start of the body, like the message of an error page. The default accepts the statuses
+/// of `Response::ok`.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{fetch_with, RequestInit, ValidateStatus};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let response = fetch_with("https://www.google.com/", RequestInit {
+///     validate_status: Some(ValidateStatus::new(|status| status < 500)),
+///     ..Default::default()
+/// }).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ValidateStatus {
+    validate: Arc<dyn Fn(u16) -> bool + Send + Sync>
+}
+
+impl ValidateStatus {
+    /// Accepts the statuses for which `validate` returns `true`.
+    pub fn new(validate: impl Fn(u16) -> bool + Send + Sync + 'static) -> ValidateStatus {
+        ValidateStatus { validate: Arc::new(validate) }
+    }
+
+    /// Accepts the statuses in the range `200` to `299`.
+    pub fn ok() -> ValidateStatus {
+        ValidateStatus::new(|status| (200..300).contains(&status))
+    }
+
+    /// Returns `response` if its status is accepted, or fails with the start of its body.
+    pub(crate) async fn check(&self, mut response: Response) -> Result<Response> {
+        if (self.validate)(response.status) {
+            return Ok(response);
+        }
+        let mut snippet = Vec::new();
+        if let Some(mut body) = response.body.take() {
+            while snippet.len() < BODY_SNIPPET_LENGTH {
+                match body.next().await {
+                    Some(Ok(chunk)) => snippet.extend_from_slice(&chunk),
+                    _ => break
+                }
+            }
+        }
+        snippet.truncate(BODY_SNIPPET_LENGTH);
+        // A character cut in half by the truncation is dropped rather than replaced.
+        let snippet = match std::str::from_utf8(&snippet) {
+            Ok(snippet) => snippet.to_string(),
+            Err(error) if error.error_len().is_none() => {
+                String::from_utf8_lossy(&snippet[..error.valid_up_to()]).into_owned()
+            },
+            Err(_) => String::from_utf8_lossy(&snippet).into_owned()
+        };
+        Err(Error::http_status(response.status, response.url, snippet).with_attempts(response.attempts))
+    }
+}
+
+impl Default for ValidateStatus {
+    fn default() -> Self {
+        ValidateStatus::ok()
+    }
+}
+
+impl fmt::Debug for ValidateStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValidateStatus").finish_non_exhaustive()
+    }
+}
+
+impl Response {
+    /// Creates a response from a body and the options of `init`, e.g. for a `MockFetch`.
+    ///
+    /// The body's `content-type` is added to the headers when they set none themselves.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use js_lib::{Response, ResponseInit};
+    /// let response = Response::new("not found", ResponseInit { status: 404, ..Default::default() });
+    /// assert!(!response.ok());
+    /// ```
+    pub fn new(body: impl Into<Body>, init: ResponseInit) -> Response {
+        let body = body.into();
+        let mut headers = init.headers;
+        if let Some(content_type) = body.content_type() {
+            if !headers.has("content-type") {
+                headers.append("content-type", content_type).expect("the content types of bodies are valid");
+            }
+        }
+        Response::from_parts(init.status, headers, String::new(), body.into_stream())
+    }
+
+    pub(crate) fn from_reqwest(response: reqwest::Response) -> Response {
+        let status = response.status().as_u16();
+        let headers = Headers::from_header_map(response.headers());
+        let url = response.url().to_string();
+        let chunks = response.bytes_stream().map(|chunk| match chunk {
+            Ok(chunk) => Ok(chunk),
+            Err(error) => Err(Error::from(error))
+        });
+        Response::from_parts(status, headers, url, ReadableStream::new(chunks))
+    }
+
+    pub(crate) fn from_parts(status: u16, headers: Headers, url: String, body: ReadableStream) -> Response {
+        Response { status, headers, url, redirected: false, attempts: Vec::new(), body: Some(body) }
+    }
+
+    /// Fails reading the body with `Error::TimeoutError` once `deadline` passes.
+    pub(crate) fn with_deadline(mut self, deadline: Option<Instant>) -> Response {
+        if let Some(deadline) = deadline {
+            self.body = self.body.map(|body| body.with_deadline(deadline));
+        }
+        self
+    }
+
+    pub(crate) fn with_attempts(mut self, attempts: Vec<RetryAttempt>) -> Response {
+        self.attempts = attempts;
+        self
+    }
+
+    /// Sets the url of a response which was created without one.
+    pub(crate) fn with_url(mut self, url: &str) -> Response {
+        if self.url.is_empty() {
+            self.url = url.to_string();
+        }
+        self
+    }
+
+    /// Reports the progress of reading the body to `listener`.
+    pub(crate) fn with_progress(mut self, listener: Option<&ProgressListener>) -> Response {
+        if let Some(listener) = listener {
+            let total = self.headers.get("content-length").and_then(|length| length.parse().ok());
+            self.body = self.body.map(|body| listener.observe(body, total));
+        }
+        self
+    }
+
+    /// Replaces the body, keeping the status, headers and url.
+    pub(crate) fn with_body(mut self, body: ReadableStream) -> Response {
+        self.body = Some(body);
+        self
+    }
+
+    /// Marks whether the response was redirected, and fails its body once `signal` is aborted.
+    pub(crate) fn finish(mut self, redirected: bool, signal: Option<AbortSignal>) -> Response {
+        self.redirected = redirected;
+        self.body = self.body.map(|body| body.with_signal(signal));
+        self
+    }
+
+    /// The http status code, e.g. `200`.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// The reason phrase of the status code, e.g. `"OK"`, or an empty string when it is unknown.
+    pub fn status_text(&self) -> &'static str {
+        match reqwest::StatusCode::from_u16(self.status) {
+            Ok(status) => status.canonical_reason().unwrap_or(""),
+            Err(_) => ""
+        }
+    }
+
+    /// Whether the status code is in the range `200` to `299`.
+    pub fn ok(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// The response headers.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// The final url of the response, after any redirects were followed.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Whether the response is the result of following one or more redirects.
+    pub fn redirected(&self) -> bool {
+        self.redirected
+    }
+
+    /// The attempts which failed before this response, when the request was retried by its
+    /// `RetryPolicy`.
+    pub fn attempts(&self) -> &[RetryAttempt] {
+        &self.attempts
+    }
+
+    /// Takes the body as a stream of chunks, or `None` if the body has already been used.
+    ///
+    /// Once taken, the body is used and can not be read again by any other method.
+    pub fn body(&mut self) -> Option<ReadableStream> {
+        self.body.take()
+    }
+
+    /// Whether the body has already been used.
+    pub fn body_used(&self) -> bool {
+        self.body.is_none()
+    }
+
+    /// Reads the body as utf-8 text.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - the body has already been used
+    /// - there is an error reading the body
+    /// - the request's signal is aborted
+    pub async fn text(self) -> Result<String> {
+        let bytes = self.bytes().await?;
+        let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    /// Reads the body and deserializes it as json into type T.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use js_lib::fetch;
+    /// # async fn example() -> Result<(), js_lib::Error> {
+    /// let words = fetch("https://random-word-api.herokuapp.com/word").await?
+    ///     .json::<Vec<String>>().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - the body has already been used
+    /// - there is an error reading the body
+    /// - the request's signal is aborted
+    /// - there is an error parsing the body as json
+    pub async fn json<T>(self) -> Result<T>
+        where T: serde::de::DeserializeOwned {
+        let (status, url) = (self.status, self.url.clone());
+        let bytes = self.bytes().await?;
+        match serde_json::from_slice::<T>(&bytes) {
+            Ok(data_struct) => Ok(data_struct),
+            Err(error) => Err(Error::from(error).with_response(status, &url))
+        }
+    }
+
+    /// Reads the body as bytes.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - the body has already been used
+    /// - there is an error reading the body
+    /// - the request's signal is aborted
+    pub async fn bytes(self) -> Result<Bytes> {
+        Ok(Bytes::from(self.array_buffer().await?))
+    }
+
+    /// Reads the body into an owned buffer of bytes.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - the body has already been used
+    /// - there is an error reading the body
+    /// - the request's signal is aborted
+    pub async fn array_buffer(self) -> Result<Vec<u8>> {
+        let (status, url) = (self.status, self.url);
+        let result = match self.body {
+            Some(body) => body.read_all().await,
+            None => Err(Error::type_error("body has already been used"))
+        };
+        match result {
+            Ok(bytes) => Ok(bytes),
+            Err(error) => Err(error.with_response(status, &url))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{response, TestServer};
+    use crate::{fetch, fetch_with, FetchClient, Method, RequestInit};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn not_found_is_not_ok() {
+        let server = TestServer::start(|_| response(404, &[("x-reason", "missing")], b"gone")).await;
+        let response = fetch(&server.url("/missing")).await.unwrap();
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.status_text(), "Not Found");
+        assert!(!response.ok());
+        assert_eq!(response.headers().get("X-Reason").as_deref(), Some("missing"));
+        assert_eq!(response.url(), server.url("/missing"));
+        assert!(!response.redirected());
+        assert_eq!(response.text().await.unwrap(), "gone");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn redirected_json() {
+        let server = TestServer::start(|request| match request.target.as_str() {
+            "/old" => response(302, &[("location", "/new")], b""),
+            _ => response(200, &[("content-type", "application/json")], br#"["a","b"]"#)
+        }).await;
+        let response = fetch(&server.url("/old")).await.unwrap();
+        assert!(response.ok());
+        assert!(response.redirected());
+        assert_eq!(response.url(), server.url("/new"));
+        assert_eq!(response.json::<Vec<String>>().await.unwrap(), ["a", "b"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rejected_statuses_fail_with_a_snippet() {
+        let page = format!("<h1>Internal Server Error!</h1>{}", "é".repeat(300));
+        let server = TestServer::start(move |request| match request.target.as_str() {
+            "/missing" => response(404, &[], b"gone"),
+            _ => response(500, &[], page.as_bytes())
+        }).await;
+        let error = fetch_with(&server.url("/broken"), RequestInit {
+            validate_status: Some(ValidateStatus::ok()),
+            ..Default::default()
+        }).await.unwrap_err();
+        match &error {
+            Error::HttpStatus { status, url, body_snippet, .. } => {
+                assert_eq!(*status, 500);
+                assert_eq!(*url, server.url("/broken"));
+                assert!(body_snippet.starts_with("<h1>Internal Server Error!</h1>é"));
+                assert_eq!(body_snippet.len(), 511);
+            },
+            other => panic!("expected a status error, got {:?}", other)
+        }
+        assert_eq!(error.method(), Some(&Method::GET));
+        let client = FetchClient::builder()
+            .validate_status(ValidateStatus::new(|status| status < 500))
+            .build()
+            .unwrap();
+        assert_eq!(client.fetch(&server.url("/missing")).await.unwrap().status(), 404);
+        let result = client.fetch(&server.url("/broken")).await;
+        assert!(matches!(result, Err(Error::HttpStatus { status: 500, .. })));
+        assert!(fetch(&server.url("/broken")).await.is_ok());
+    }
+}
@@ -0,0 +1,245 @@
+//! The `RetryPolicy` which retries a failed `fetch` with exponential backoff.
+
+use crate::date::{parse_date, unix_time};
+use crate::{Error, Method, RequestInit, Response, Result};
+use std::future::Future;
+use std::io::ErrorKind;
+use std::time::Duration;
+
+/// When and how often a failed request is retried.
+///
+/// Only requests with an idempotent method (GET, HEAD, OPTIONS, TRACE, PUT and DELETE) are
+/// retried, and never a request with a streamed body, which can only be sent once. A request is
+/// retried when its response status is one of `statuses`, or when it fails with an io error of
+/// one of the kinds in `errors`. Between attempts the delay starts at `initial_backoff` and is
+/// multiplied by `multiplier` after each attempt, up to `max_backoff`, unless the response has
+/// a `retry-after` header which gives the delay instead, also waited for at most `max_backoff`.
+///
+/// The attempts which failed before the last one are kept in `Response::attempts`, or in
+/// `Error::attempts` when the request fails.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{fetch_with, RequestInit, RetryPolicy};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let response = fetch_with("https://www.google.com/", RequestInit {
+///     retry: Some(RetryPolicy {
+///         max_attempts: 5,
+///         statuses: vec![429, 502, 503, 504],
+///         ..Default::default()
+///     }),
+///     ..Default::default()
+/// }).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// The most times the request is sent, including the first, `3` by default.
+    pub max_attempts: u32,
+    /// The delay before the first retry, `100ms` by default.
+    pub initial_backoff: Duration,
+    /// The longest delay between two attempts, `10s` by default.
+    pub max_backoff: Duration,
+    /// The factor the delay grows by after each attempt, `2.0` by default.
+    pub multiplier: f64,
+    /// Whether each delay is randomized between zero and its full length, so clients which
+    /// failed together do not retry together, `true` by default.
+    pub jitter: bool,
+    /// The response statuses which are retried, `502`, `503` and `504` by default.
+    pub statuses: Vec<u16>,
+    /// The kinds of io error which are retried, a reset, refused, aborted or timed out
+    /// connection by default.
+    pub errors: Vec<ErrorKind>,
+    /// Whether the delay of a `retry-after` header is waited instead of the backoff, `true` by
+    /// default.
+    pub honor_retry_after: bool
+}
+
+/// The outcome of one failed attempt of a retried request.
+#[derive(Debug)]
+pub enum RetryAttempt {
+    /// The attempt received a response with a retried status.
+    Status(u16),
+    /// The attempt failed with an error.
+    Error(Error)
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: true,
+            statuses: vec![502, 503, 504],
+            errors: vec![
+                ErrorKind::ConnectionReset,
+                ErrorKind::ConnectionRefused,
+                ErrorKind::ConnectionAborted,
+                ErrorKind::TimedOut
+            ],
+            honor_retry_after: true
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether `error` is one of the retried io error kinds.
+    fn retries_error(&self, error: &Error) -> bool {
+        let kind = match error {
+            Error::TimeoutError { .. } => Some(ErrorKind::TimedOut),
+            Error::NetworkError { .. } => io_error_kind(error),
+            _ => None
+        };
+        kind.is_some_and(|kind| self.errors.contains(&kind))
+    }
+
+    /// The delay before retrying after `attempt`, counting from 1.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.max(1.0).powi(attempt as i32 - 1);
+        let backoff = self.initial_backoff.mul_f64(factor.min(u32::MAX as f64)).min(self.max_backoff);
+        if !self.jitter {
+            return backoff;
+        }
+        backoff.mul_f64(fastrand::f64())
+    }
+}
+
+/// Sends the request with `send` until it succeeds or `policy` stops retrying it.
+pub(crate) async fn retry<F, Fut>(init: RequestInit, send: F) -> Result<Response>
+    where F: Fn(RequestInit) -> Fut, Fut: Future<Output = Result<Response>> {
+    let policy = match init.retry.clone() {
+        Some(policy) if is_idempotent(&init.method) => policy,
+        _ => return send(init).await
+    };
+    let mut attempts = Vec::new();
+    let mut init = init;
+    let mut attempt = 1;
+    loop {
+        // A streamed body can only be sent once, so such a request gets a single attempt.
+        let next = init.try_clone().filter(|_| attempt < policy.max_attempts);
+        let (next, retry_after) = match (send(init).await, next) {
+            (Ok(response), Some(next)) if policy.statuses.contains(&response.status()) => {
+                attempts.push(RetryAttempt::Status(response.status()));
+                (next, response.headers().get("retry-after").filter(|_| policy.honor_retry_after))
+            },
+            (Ok(response), _) => return Ok(response.with_attempts(attempts)),
+            (Err(error), Some(next)) if policy.retries_error(&error) => {
+                attempts.push(RetryAttempt::Error(error));
+                (next, None)
+            },
+            (Err(error), _) => return Err(error.with_attempts(attempts))
+        };
+        // A server asking for a longer delay than the policy allows is retried sooner rather than
+        // holding the request for as long as it asks.
+        let delay = match retry_after.and_then(|value| parse_retry_after(&value)) {
+            Some(delay) => delay.min(policy.max_backoff),
+            None => policy.backoff(attempt)
+        };
+        tokio::time::sleep(delay).await;
+        init = next;
+        attempt += 1;
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    let idempotent = [Method::GET, Method::HEAD, Method::OPTIONS, Method::TRACE, Method::PUT, Method::DELETE];
+    idempotent.contains(method)
+}
+
+/// The delay of a `retry-after` header, given in seconds or as an http date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    match value.trim().parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => {
+            let seconds = parse_date(value)? - unix_time();
+            Some(Duration::from_secs(seconds.max(0) as u64))
+        }
+    }
+}
+
+/// The kind of the io error which caused `error`, if any.
+fn io_error_kind(error: &Error) -> Option<ErrorKind> {
+    let mut source = std::error::Error::source(error);
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<std::io::Error>() {
+            return Some(error.kind());
+        }
+        source = error.source();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fetch_with, ValidateStatus};
+    use crate::test_server::{response, TestServer};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn quick_policy() -> Option<RetryPolicy> {
+        let (initial_backoff, max_backoff) = (Duration::from_millis(1), Duration::from_millis(10));
+        Some(RetryPolicy { initial_backoff, max_backoff, ..Default::default() })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unavailable_responses_are_retried() {
+        let count = AtomicUsize::new(0);
+        let server = TestServer::start(move |request| match count.fetch_add(1, Ordering::SeqCst) {
+            _ if request.method == "POST" || request.target == "/down" => response(503, &[], b"down"),
+            0 => response(503, &[("retry-after", "3600")], b""),
+            1 => response(502, &[], b""),
+            _ => response(200, &[], b"done")
+        }).await;
+        let response = fetch_with(&server.url("/"), RequestInit {
+            retry: quick_policy(),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(response.attempts().len(), 2);
+        assert_eq!(response.text().await.unwrap(), "done");
+        assert_eq!(server.received().len(), 3);
+        let response = fetch_with(&server.url("/"), RequestInit {
+            method: Method::POST,
+            retry: quick_policy(),
+            ..Default::default()
+        }).await.unwrap();
+        assert_eq!(response.status(), 503);
+        assert_eq!(server.received().len(), 4);
+        let error = fetch_with(&server.url("/down"), RequestInit {
+            retry: quick_policy(),
+            validate_status: Some(ValidateStatus::ok()),
+            ..Default::default()
+        }).await.unwrap_err();
+        assert_eq!(error.status(), Some(503));
+        assert!(matches!(error.attempts(), [RetryAttempt::Status(503), RetryAttempt::Status(503)]));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn every_failed_attempt_is_reported() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let result = fetch_with(&url, RequestInit { retry: quick_policy(), ..Default::default() }).await;
+        let error = result.unwrap_err();
+        assert_eq!(error.name(), "NetworkError");
+        assert_eq!(error.attempts().len(), 2);
+        let refused = |attempt: &RetryAttempt| {
+            matches!(attempt, RetryAttempt::Error(Error::NetworkError { .. }))
+        };
+        assert!(error.attempts().iter().all(refused));
+        assert!(error.to_string().ends_with("after 2 failed attempts"));
+    }
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        let max_backoff = Duration::from_millis(300);
+        let policy = RetryPolicy { jitter: false, max_backoff, ..Default::default() };
+        let delays = (1..=4).map(|attempt| policy.backoff(attempt).as_millis()).collect::<Vec<_>>();
+        assert_eq!(delays, [100, 200, 300, 300]);
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"), Some(Duration::ZERO));
+    }
+}
@@ -0,0 +1,218 @@
+//! The fetches of `data:` and `file:` urls, which are answered locally rather than over the
+//! network.
+
+use crate::transport::Transport;
+use crate::{Error, Headers, Method, ReadableStream, Request, Response, ResponseInit, Result};
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use reqwest::Url;
+use std::sync::Arc;
+
+/// The forgiving base64 of the infra standard, where the padding is optional.
+const FORGIVING_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &base64::alphabet::STANDARD,
+    GeneralPurposeConfig::new()
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent)
+        .with_decode_allow_trailing_bits(true)
+);
+
+/// The content types of file extensions, for the `content-type` of a `file:` url.
+const CONTENT_TYPES: [(&str, &str); 26] = [
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ico", "image/x-icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "text/javascript"),
+    ("json", "application/json"),
+    ("md", "text/markdown"),
+    ("mjs", "text/javascript"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("toml", "application/toml"),
+    ("txt", "text/plain"),
+    ("wasm", "application/wasm"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("xml", "application/xml"),
+    ("zip", "application/zip")
+];
+
+/// Whether `url` is answered locally instead of being sent by a transport.
+pub(crate) fn is_local(url: &Url) -> bool {
+    matches!(url.scheme(), "data" | "file")
+}
+
+/// The transport answering `data:` urls, and `file:` urls when they are allowed, before sending
+/// any other request with its inner transport.
+#[derive(Debug)]
+pub(crate) struct LocalTransport {
+    inner: Arc<dyn Transport>,
+    allow_file_urls: bool
+}
+
+impl LocalTransport {
+    pub(crate) fn wrap(inner: Arc<dyn Transport>, allow_file_urls: bool) -> Arc<dyn Transport> {
+        Arc::new(LocalTransport { inner, allow_file_urls })
+    }
+}
+
+impl Transport for LocalTransport {
+    fn send(&self, request: Request) -> BoxFuture<'static, Result<Response>> {
+        let url = match Url::parse(request.url()) {
+            Ok(url) if is_local(&url) => url,
+            _ => return self.inner.send(request)
+        };
+        let allow_file_urls = self.allow_file_urls;
+        Box::pin(async move {
+            if url.scheme() == "file" && !allow_file_urls {
+                let message = format!("{} can not be fetched, file urls are not allowed by the client", url);
+                return Err(Error::type_error(message));
+            }
+            fetch(request.method(), &url).await
+        })
+    }
+}
+
+/// Fetches a `data:` or `file:` url.
+async fn fetch(method: &Method, url: &Url) -> Result<Response> {
+    let response = match url.scheme() {
+        "data" => data_fetch(url)?,
+        _ => file_fetch(method, url).await?
+    };
+    Ok(response.with_url(url.as_str()))
+}
+
+/// Decodes a `data:` url as the data url processor of the fetch standard, for any method.
+fn data_fetch(url: &Url) -> Result<Response> {
+    let input = &url.as_str()["data:".len()..];
+    let input = input.split_once('#').map_or(input, |(input, _)| input);
+    let (mime_type, data) = match input.split_once(',') {
+        Some(parts) => parts,
+        None => return Err(Error::type_error(format!("the data url {:?} has no comma", url.as_str())))
+    };
+    let mut mime_type = mime_type.trim_matches(|c: char| c.is_ascii_whitespace()).to_string();
+    let mut body = percent_encoding::percent_decode_str(data).collect::<Vec<u8>>();
+    let base64 = mime_type.rsplit_once(';').filter(|(_, last)| last.trim().eq_ignore_ascii_case("base64"));
+    if let Some((rest, _)) = base64 {
+        body.retain(|byte| !byte.is_ascii_whitespace());
+        body = match FORGIVING_BASE64.decode(&body) {
+            Ok(body) => body,
+            Err(error) => return Err(Error::type_error(format!("invalid base64 in a data url: {}", error)))
+        };
+        mime_type = rest.to_string();
+    }
+    if mime_type.starts_with(';') {
+        mime_type = format!("text/plain{}", mime_type);
+    }
+    if !mime_type.contains('/') {
+        mime_type = "text/plain;charset=US-ASCII".to_string();
+    }
+    let headers = Headers::from([("content-type", mime_type.as_str())]);
+    Ok(Response::new(Bytes::from(body), ResponseInit { status: 200, headers }))
+}
+
+/// Reads the file of a `file:` url, streaming its content, for a GET or HEAD request.
+async fn file_fetch(method: &Method, url: &Url) -> Result<Response> {
+    if *method != Method::GET && *method != Method::HEAD {
+        return Err(Error::type_error(format!("a file url can not be fetched with {}", method)));
+    }
+    let path = match url.to_file_path() {
+        Ok(path) => path,
+        Err(_) => return Err(Error::type_error(format!("the file url {:?} has no valid path", url.as_str())))
+    };
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(error) => return Err(Error::from(error))
+    };
+    let metadata = match file.metadata().await {
+        Ok(metadata) => metadata,
+        Err(error) => return Err(Error::from(error))
+    };
+    if metadata.is_dir() {
+        return Err(Error::type_error(format!("{} is a directory", path.display())));
+    }
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    let content_type = CONTENT_TYPES.iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        .map_or("application/octet-stream", |(_, content_type)| content_type);
+    let headers = Headers::from([
+        ("content-type", content_type),
+        ("content-length", &metadata.len().to_string())
+    ]);
+    let body = if *method == Method::HEAD {
+        ReadableStream::new(futures_util::stream::empty())
+    } else {
+        ReadableStream::from_async_read(file)
+    };
+    Ok(Response::new(body, ResponseInit { status: 200, headers }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fetch, FetchClient, Next, RequestInit};
+    use crate::test_server::{response, TestServer};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn data_urls_are_decoded() {
+        let response = fetch("data:application/json;base64,eyJuYW1lIjoianNfbGliIn0").await.unwrap();
+        assert_eq!(response.headers().get("content-type").as_deref(), Some("application/json"));
+        assert_eq!(response.text().await.unwrap(), r#"{"name":"js_lib"}"#);
+        let response = fetch("data:,hello%20world%21#fragment").await.unwrap();
+        assert_eq!(response.headers().get("content-type").as_deref(), Some("text/plain;charset=US-ASCII"));
+        assert_eq!(response.text().await.unwrap(), "hello world!");
+        let response = fetch("data:;charset=utf-8;base64, aMOp bGxv").await.unwrap();
+        assert_eq!(response.headers().get("content-type").as_deref(), Some("text/plain;charset=utf-8"));
+        assert_eq!(response.text().await.unwrap(), "héllo");
+        assert!(matches!(fetch("data:text/plain").await, Err(Error::TypeError { .. })));
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let client = FetchClient::builder()
+            .interceptor({
+                let seen = seen.clone();
+                move |request: Request, next: Next| {
+                    seen.lock().unwrap().push(request.url().to_string());
+                    next.run(request)
+                }
+            })
+            .build()
+            .unwrap();
+        assert_eq!(client.fetch("data:,seen").await.unwrap().text().await.unwrap(), "seen");
+        assert_eq!(*seen.lock().unwrap(), ["data:,seen"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn file_urls_are_streamed() {
+        let directory = std::env::temp_dir().join(format!("js_lib-scheme-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("fixture.json");
+        std::fs::write(&path, br#"["a","b"]"#).unwrap();
+        let url = Url::from_file_path(&path).unwrap();
+        assert!(matches!(fetch(url.as_str()).await, Err(Error::TypeError { .. })));
+        let client = FetchClient::builder().allow_file_urls(true).build().unwrap();
+        let fixture = client.fetch(url.as_str()).await.unwrap();
+        assert_eq!(fixture.url(), url.as_str());
+        assert_eq!(fixture.headers().get("content-type").as_deref(), Some("application/json"));
+        assert_eq!(fixture.headers().get("content-length").as_deref(), Some("9"));
+        assert_eq!(fixture.json::<Vec<String>>().await.unwrap(), ["a", "b"]);
+        let missing = Url::from_file_path(directory.join("missing.txt")).unwrap();
+        assert!(matches!(client.fetch(missing.as_str()).await, Err(Error::NetworkError { .. })));
+        let post = RequestInit { method: Method::POST, ..Default::default() };
+        let post = client.fetch_with(url.as_str(), post).await;
+        assert!(matches!(post, Err(Error::TypeError { .. })));
+        std::fs::remove_dir_all(&directory).unwrap();
+        let server = TestServer::start(move |_| response(302, &[("location", url.as_str())], b"")).await;
+        assert!(matches!(client.fetch(&server.url("/")).await, Err(Error::TypeError { .. })));
+    }
+}
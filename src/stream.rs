@@ -0,0 +1,202 @@
+//! The javascript-like `ReadableStream` of body chunks.
+
+use crate::{AbortSignal, Error, Result};
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use futures_util::stream::{BoxStream, Stream, StreamExt};
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::AsyncRead;
+use tokio::time::Instant;
+use tokio_util::io::ReaderStream;
+
+/// A stream of body chunks, mirroring the javascript `ReadableStream`.
+///
+/// Chunks are only pulled from their source when the stream is read, so a slow reader applies
+/// backpressure all the way to the connection. The stream implements `futures::Stream`, or can
+/// be read with a javascript-like reader loop.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::fetch;
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let mut response = fetch("https://www.google.com/").await?;
+/// if let Some(mut body) = response.body() {
+///     let mut reader = body.get_reader();
+///     while let Some(chunk) = reader.read().await? {
+///         println!("received {} bytes", chunk.len());
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct ReadableStream {
+    chunks: BoxStream<'static, Result<Bytes>>,
+    aborted: Option<BoxFuture<'static, Error>>,
+    done: bool
+}
+
+impl ReadableStream {
+    /// Creates a stream reading its chunks from another stream.
+    ///
+    /// An error yielded by `chunks` fails the stream, and any request uploading it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use js_lib::ReadableStream;
+    /// let chunks = vec![Ok(bytes::Bytes::from("hello ")), Ok(bytes::Bytes::from("world"))];
+    /// let stream = ReadableStream::new(futures_util::stream::iter(chunks));
+    /// ```
+    pub fn new<S>(chunks: S) -> ReadableStream
+        where S: Stream<Item = Result<Bytes>> + Send + 'static {
+        ReadableStream { chunks: chunks.boxed(), aborted: None, done: false }
+    }
+
+    /// Creates a stream reading its chunks from an `AsyncRead`, like a file or a socket.
+    pub fn from_async_read<R>(reader: R) -> ReadableStream
+        where R: AsyncRead + Send + 'static {
+        ReadableStream::new(ReaderStream::new(reader).map(|chunk| match chunk {
+            Ok(chunk) => Ok(chunk),
+            Err(error) => Err(Error::from(error))
+        }))
+    }
+
+    /// Locks the stream to a reader, which reads chunks until the stream is done.
+    ///
+    /// The stream stays locked, and can not be read otherwise, for as long as the reader lives.
+    pub fn get_reader(&mut self) -> ReadableStreamDefaultReader<'_> {
+        ReadableStreamDefaultReader { stream: self }
+    }
+
+    /// Fails the stream with the signal's error as soon as `signal` is aborted.
+    pub(crate) fn with_signal(mut self, signal: Option<AbortSignal>) -> ReadableStream {
+        if let Some(signal) = signal {
+            self.aborted = Some(Box::pin(async move { signal.aborted_error().await }));
+        }
+        self
+    }
+
+    /// Fails the stream with `Error::TimeoutError` once `deadline` passes, unless it is aborted
+    /// first.
+    pub(crate) fn with_deadline(mut self, deadline: Instant) -> ReadableStream {
+        let aborted = self.aborted.take();
+        self.aborted = Some(Box::pin(async move {
+            let aborted = async move {
+                match aborted {
+                    Some(aborted) => aborted.await,
+                    None => std::future::pending().await
+                }
+            };
+            match tokio::time::timeout_at(deadline, aborted).await {
+                Ok(error) => error,
+                Err(elapsed) => Error::from(elapsed)
+            }
+        }));
+        self
+    }
+
+    /// Reads every remaining chunk into one buffer.
+    pub(crate) async fn read_all(mut self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        while let Some(chunk) = self.next().await {
+            buffer.extend_from_slice(&chunk?);
+        }
+        Ok(buffer)
+    }
+}
+
+impl Stream for ReadableStream {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        if let Some(aborted) = self.aborted.as_mut() {
+            if let Poll::Ready(error) = aborted.as_mut().poll(cx) {
+                self.done = true;
+                return Poll::Ready(Some(Err(error)));
+            }
+        }
+        let chunk = self.chunks.poll_next_unpin(cx);
+        if let Poll::Ready(None) | Poll::Ready(Some(Err(_))) = chunk {
+            self.done = true;
+        }
+        chunk
+    }
+}
+
+impl fmt::Debug for ReadableStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadableStream").field("done", &self.done).finish()
+    }
+}
+
+/// Reads the chunks of a locked `ReadableStream`, mirroring the javascript
+/// `ReadableStreamDefaultReader`.
+#[derive(Debug)]
+pub struct ReadableStreamDefaultReader<'a> {
+    stream: &'a mut ReadableStream
+}
+
+impl ReadableStreamDefaultReader<'_> {
+    /// Reads the next chunk, or `None` once the stream is done.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - there is an error reading from the source of the stream
+    /// - the request's signal is aborted
+    pub async fn read(&mut self) -> Result<Option<Bytes>> {
+        match self.stream.next().await {
+            Some(Ok(chunk)) => Ok(Some(chunk)),
+            Some(Err(error)) => Err(error),
+            None => Ok(None)
+        }
+    }
+
+    /// Unlocks the stream, so it can be read again by another reader.
+    pub fn release_lock(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_server::TestServer;
+    use crate::fetch;
+    use futures_util::StreamExt;
+    use std::time::Duration;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn read_chunks_before_the_body_ends() {
+        let head = b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n".to_vec();
+        let server = TestServer::start_parts(move |_| vec![
+            (Duration::ZERO, head.clone()),
+            (Duration::ZERO, b"5\r\nfirst\r\n".to_vec()),
+            (Duration::from_millis(100), b"6\r\nsecond\r\n0\r\n\r\n".to_vec())
+        ]).await;
+        let mut response = fetch(&server.url("/poll")).await.unwrap();
+        let mut body = response.body().unwrap();
+        let mut reader = body.get_reader();
+        assert_eq!(reader.read().await.unwrap().unwrap(), "first");
+        assert_eq!(reader.read().await.unwrap().unwrap(), "second");
+        assert!(reader.read().await.unwrap().is_none());
+        reader.release_lock();
+        assert!(body.next().await.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn body_can_only_be_consumed_once() {
+        let server = TestServer::start(|_| crate::test_server::response(200, &[], b"once")).await;
+        let mut response = fetch(&server.url("/")).await.unwrap();
+        assert!(!response.body_used());
+        let chunks = response.body().unwrap().collect::<Vec<_>>().await;
+        assert_eq!(chunks.len(), 1);
+        assert!(response.body_used());
+        assert!(response.body().is_none());
+        assert!(response.text().await.is_err());
+    }
+}
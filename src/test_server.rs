@@ -0,0 +1,131 @@
+//! A minimal HTTP/1.1 server on the loopback interface, so `fetch` can be tested offline.
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// A request as it was received by the `TestServer`.
+#[derive(Debug, Clone)]
+pub(crate) struct ReceivedRequest {
+    pub method: String,
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>
+}
+
+impl ReceivedRequest {
+    /// The first value of a header, matched case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A server answering every request with the bytes returned by its handler.
+///
+/// `start_parts` writes the response in parts, each after a delay, to simulate slow servers.
+#[derive(Debug)]
+pub(crate) struct TestServer {
+    addr: SocketAddr,
+    received: Arc<Mutex<Vec<ReceivedRequest>>>
+}
+
+impl TestServer {
+    pub async fn start<F>(handler: F) -> TestServer
+        where F: Fn(&ReceivedRequest) -> Vec<u8> + Send + Sync + 'static {
+        TestServer::start_parts(move |request| vec![(Duration::ZERO, handler(request))]).await
+    }
+
+    pub async fn start_parts<F>(handler: F) -> TestServer
+        where F: Fn(&ReceivedRequest) -> Vec<(Duration, Vec<u8>)> + Send + Sync + 'static {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let handler = Arc::new(handler);
+        let log = received.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                let log = log.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut reader = BufReader::new(read);
+                    if let Some(request) = read_request(&mut reader).await {
+                        let parts = handler(&request);
+                        log.lock().unwrap().push(request);
+                        for (delay, bytes) in parts {
+                            tokio::time::sleep(delay).await;
+                            if write.write_all(&bytes).await.is_err() {
+                                return;
+                            }
+                        }
+                        let _ = write.shutdown().await;
+                    }
+                });
+            }
+        });
+        TestServer { addr, received }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn received(&self) -> Vec<ReceivedRequest> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+/// Builds a complete response which closes the connection once written.
+pub(crate) fn response(status: u16, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+    let mut head = format!("HTTP/1.1 {} Test\r\ncontent-length: {}\r\nconnection: close\r\n", status, body.len());
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+async fn read_request<R>(reader: &mut R) -> Option<ReceivedRequest>
+    where R: AsyncBufReadExt + Unpin {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?.to_string();
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    let mut request = ReceivedRequest { method, target, headers, body: Vec::new() };
+    if let Some(length) = request.header("content-length") {
+        let mut body = vec![0; length.parse().ok()?];
+        reader.read_exact(&mut body).await.ok()?;
+        request.body = body;
+    } else if request.header("transfer-encoding").is_some_and(|value| value.contains("chunked")) {
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).await.ok()?;
+            let size = usize::from_str_radix(size.trim(), 16).ok()?;
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk).await.ok()?;
+            if size == 0 {
+                break;
+            }
+            request.body.extend_from_slice(&chunk[..size]);
+        }
+    }
+    Some(request)
+}
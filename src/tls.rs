@@ -0,0 +1,531 @@
+//! The `TlsOptions` of the connections a `FetchClient` makes to https urls.
+
+use crate::{Error, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{CertificateError, ClientConfig, DigitallySignedStruct, SignatureScheme};
+use rustls_platform_verifier::Verifier;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::Arc;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// How the server of a https url is verified, and how the client identifies itself to it.
+///
+/// Without options, a server certificate is trusted when it is issued by one of the root
+/// certificates of the platform. Roots can be added for a private certificate authority, a
+/// client certificate can be presented for mutual TLS, and the server can be pinned to the
+/// SHA-256 hashes of certificates or their public keys, one of which must be in its chain.
+///
+/// The options are set for every request of a client with `FetchClientBuilder::tls`, or for a
+/// single request with `RequestInit::tls`.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::{FetchClient, TlsOptions, TlsVersion};
+/// # fn example() -> Result<(), js_lib::Error> {
+/// let client = FetchClient::builder()
+///     .tls(TlsOptions::new()
+///         .root_certificate_pem(&std::fs::read("ca.pem")?)
+///         .client_certificate_pem(&std::fs::read("client.pem")?, &std::fs::read("client.key")?)
+///         .min_version(TlsVersion::Tls1_3))
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct TlsOptions {
+    roots: Vec<Encoded>,
+    identity: Option<(Vec<u8>, Vec<u8>)>,
+    min_version: Option<TlsVersion>,
+    pins: Vec<Pin>,
+    danger_accept_invalid_certs: bool
+}
+
+/// A version of the TLS protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsVersion {
+    /// TLS 1.2.
+    Tls1_2,
+    /// TLS 1.3.
+    Tls1_3
+}
+
+/// A certificate in one of the encodings it can be given in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Encoded {
+    Pem(Vec<u8>),
+    Der(Vec<u8>)
+}
+
+/// A SHA-256 hash one of the certificates of the server must match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pin {
+    Certificate([u8; 32]),
+    Spki([u8; 32])
+}
+
+impl TlsOptions {
+    /// Creates options which trust the root certificates of the platform.
+    pub fn new() -> TlsOptions {
+        TlsOptions::default()
+    }
+
+    /// Trusts the root certificates of a PEM file, in addition to those of the platform.
+    pub fn root_certificate_pem(mut self, pem: &[u8]) -> TlsOptions {
+        self.roots.push(Encoded::Pem(pem.to_vec()));
+        self
+    }
+
+    /// Trusts a DER encoded root certificate, in addition to those of the platform.
+    pub fn root_certificate_der(mut self, der: &[u8]) -> TlsOptions {
+        self.roots.push(Encoded::Der(der.to_vec()));
+        self
+    }
+
+    /// Presents a client certificate to servers which ask for one, from the PEM of its chain,
+    /// starting with the certificate itself, and the PEM of its private key.
+    pub fn client_certificate_pem(mut self, certificates: &[u8], key: &[u8]) -> TlsOptions {
+        self.identity = Some((certificates.to_vec(), key.to_vec()));
+        self
+    }
+
+    /// Sets the oldest TLS version which is negotiated, `TlsVersion::Tls1_2` by default.
+    pub fn min_version(mut self, version: TlsVersion) -> TlsOptions {
+        self.min_version = Some(version);
+        self
+    }
+
+    /// Pins the server to the SHA-256 hash of the DER of a certificate in its chain.
+    ///
+    /// Once any pin is added, a server whose chain matches none of them is rejected. The chain is
+    /// the one verified from the server certificate to a trusted root, or only the server
+    /// certificate itself when `danger_accept_invalid_certs` is set.
+    pub fn pin_certificate_sha256(mut self, hash: [u8; 32]) -> TlsOptions {
+        self.pins.push(Pin::Certificate(hash));
+        self
+    }
+
+    /// Pins the server to the SHA-256 hash of the DER of the subject public key info of a
+    /// certificate in its chain, which stays the same when the certificate is renewed with the
+    /// same key.
+    ///
+    /// Once any pin is added, a server whose chain matches none of them is rejected, as for
+    /// `pin_certificate_sha256`.
+    pub fn pin_spki_sha256(mut self, hash: [u8; 32]) -> TlsOptions {
+        self.pins.push(Pin::Spki(hash));
+        self
+    }
+
+    /// Sets whether any server certificate is accepted, even an expired or self-signed one, or
+    /// one for another host. `false` by default.
+    ///
+    /// **WARNING**: Anyone in the network path can read and change the requests, unless the
+    /// server is pinned. Only use this for local development.
+    pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> TlsOptions {
+        self.danger_accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    /// Creates the rustls configuration of the options.
+    pub(crate) fn client_config(&self) -> Result<ClientConfig> {
+        let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+        let versions = match self.min_version {
+            Some(TlsVersion::Tls1_3) => &[&rustls::version::TLS13][..],
+            _ => rustls::DEFAULT_VERSIONS
+        };
+        let builder = ClientConfig::builder_with_provider(provider.clone());
+        let builder = match builder.with_protocol_versions(versions) {
+            Ok(builder) => builder,
+            Err(error) => return Err(Error::type_error(format!("invalid TLS options: {}", error)))
+        };
+        let roots = self.root_certificates()?;
+        let mut verifier: Arc<dyn ServerCertVerifier> = if self.danger_accept_invalid_certs {
+            Arc::new(AcceptAnyCertificate { provider: provider.clone() })
+        } else {
+            match Verifier::new_with_extra_roots(roots.clone(), provider.clone()) {
+                Ok(verifier) => Arc::new(verifier),
+                Err(error) => {
+                    return Err(Error::network_error("the root certificates could not be loaded", error));
+                }
+            }
+        };
+        if !self.pins.is_empty() {
+            // Without verification there is no chain, so only the end-entity certificate is pinned.
+            let roots = if self.danger_accept_invalid_certs {
+                None
+            } else {
+                let mut roots = roots;
+                roots.extend(rustls_native_certs::load_native_certs().certs);
+                Some(roots)
+            };
+            verifier = Arc::new(Pinned { verifier, pins: self.pins.clone(), roots, provider });
+        }
+        let builder = builder.dangerous().with_custom_certificate_verifier(verifier);
+        let mut config = match &self.identity {
+            Some((certificates, key)) => {
+                let certificates = parse_certificates(certificates)?;
+                let key = match PrivateKeyDer::from_pem_slice(key) {
+                    Ok(key) => key,
+                    Err(error) => return Err(Error::type_error(format!("invalid private key: {}", error)))
+                };
+                match builder.with_client_auth_cert(certificates, key) {
+                    Ok(config) => config,
+                    Err(error) => {
+                        return Err(Error::type_error(format!("invalid client certificate: {}", error)));
+                    }
+                }
+            },
+            None => builder.with_no_client_auth()
+        };
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(config)
+    }
+
+    fn root_certificates(&self) -> Result<Vec<CertificateDer<'static>>> {
+        let mut roots = Vec::new();
+        for root in &self.roots {
+            match root {
+                Encoded::Pem(pem) => roots.extend(parse_certificates(pem)?),
+                Encoded::Der(der) => roots.push(CertificateDer::from(der.clone()))
+            }
+        }
+        Ok(roots)
+    }
+}
+
+impl fmt::Debug for TlsOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The private key is left out, so a logged client does not leak it.
+        f.debug_struct("TlsOptions")
+            .field("roots", &self.roots.len())
+            .field("client_certificate", &self.identity.is_some())
+            .field("min_version", &self.min_version)
+            .field("pins", &self.pins)
+            .field("danger_accept_invalid_certs", &self.danger_accept_invalid_certs)
+            .finish()
+    }
+}
+
+fn parse_certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_slice_iter(pem).collect::<std::result::Result<Vec<_>, _>>();
+    match certificates {
+        Ok(certificates) if certificates.is_empty() => Err(Error::type_error("no certificate in the PEM")),
+        Ok(certificates) => Ok(certificates),
+        Err(error) => Err(Error::type_error(format!("invalid certificate PEM: {}", error)))
+    }
+}
+
+/// A verifier which only accepts servers whose chain matches one of the pins, after the
+/// verification of its inner verifier.
+///
+/// The pins are matched against the end-entity certificate and the chain built from it to one
+/// of `roots`, never against the certificates the server merely sends along, which anyone can
+/// append. Without `roots`, or when no chain can be built, only the end-entity certificate is
+/// matched.
+#[derive(Debug)]
+struct Pinned {
+    verifier: Arc<dyn ServerCertVerifier>,
+    pins: Vec<Pin>,
+    roots: Option<Vec<CertificateDer<'static>>>,
+    provider: Arc<CryptoProvider>
+}
+
+impl Pinned {
+    /// The certificates of the chain from `end_entity` to a root, as verified by webpki.
+    fn chain(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime
+    ) -> Vec<CertificateDer<'static>> {
+        let mut chain = vec![end_entity.clone().into_owned()];
+        let Some(roots) = &self.roots else { return chain };
+        let anchors = roots.iter()
+            .filter_map(|root| webpki::anchor_from_trusted_cert(root).ok().map(|anchor| (root, anchor)))
+            .collect::<Vec<_>>();
+        let trust_anchors = anchors.iter().map(|(_, anchor)| anchor.clone()).collect::<Vec<_>>();
+        let Ok(certificate) = webpki::EndEntityCert::try_from(end_entity) else { return chain };
+        let path = certificate.verify_for_usage(
+            self.provider.signature_verification_algorithms.all,
+            &trust_anchors,
+            intermediates,
+            now,
+            webpki::KeyUsage::server_auth(),
+            None,
+            None
+        );
+        if let Ok(path) = path {
+            chain.extend(path.intermediate_certificates().map(|certificate| certificate.der().into_owned()));
+            let anchor = path.anchor();
+            let root = anchors.iter().find(|(_, candidate)| {
+                candidate.subject == anchor.subject
+                    && candidate.subject_public_key_info == anchor.subject_public_key_info
+            });
+            chain.extend(root.map(|(root, _)| (*root).clone()));
+        }
+        chain
+    }
+}
+
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let verified = self.verifier
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        let pinned = self.chain(end_entity, intermediates, now).iter().any(|certificate| {
+            self.pins.iter().any(|pin| match pin {
+                Pin::Certificate(hash) => Sha256::digest(certificate).as_slice() == hash,
+                Pin::Spki(hash) => spki(certificate).is_some_and(|spki| &Sha256::digest(spki)[..] == hash)
+            })
+        });
+        if !pinned {
+            return Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls12_signature(message, certificate, signature)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls13_signature(message, certificate, signature)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.verifier.supported_verify_schemes()
+    }
+}
+
+/// A verifier which accepts any certificate, still checking the handshake is signed by it.
+#[derive(Debug)]
+struct AcceptAnyCertificate {
+    provider: Arc<CryptoProvider>
+}
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: UnixTime
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = &self.provider.signature_verification_algorithms;
+        rustls::crypto::verify_tls12_signature(message, certificate, signature, algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        certificate: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        let algorithms = &self.provider.signature_verification_algorithms;
+        rustls::crypto::verify_tls13_signature(message, certificate, signature, algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// The DER of the subject public key info of a DER certificate, or `None` if it is malformed.
+fn spki(certificate: &[u8]) -> Option<&[u8]> {
+    match X509Certificate::from_der(certificate) {
+        Ok((_, certificate)) => Some(certificate.tbs_certificate.subject_pki.raw),
+        Err(_) => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::response;
+    use crate::{fetch_with, FetchClient, RequestInit};
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair, PublicKeyData};
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::{RootCertStore, ServerConfig};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    /// A private certificate authority with a certificate for `localhost` and one for a client.
+    struct Authority {
+        ca: String,
+        ca_der: CertificateDer<'static>,
+        server: (CertificateDer<'static>, KeyPair),
+        client: (String, String)
+    }
+
+    impl Authority {
+        fn new() -> Authority {
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+            let server_key = KeyPair::generate().unwrap();
+            let server = CertificateParams::new(vec!["localhost".to_string()]).unwrap()
+                .signed_by(&server_key, &ca)
+                .unwrap();
+            let client_key = KeyPair::generate().unwrap();
+            let client = CertificateParams::new(vec!["client".to_string()]).unwrap()
+                .signed_by(&client_key, &ca)
+                .unwrap();
+            Authority {
+                ca: ca.pem(),
+                ca_der: ca.der().clone(),
+                server: (server.der().clone(), server_key),
+                client: (client.pem(), client_key.serialize_pem())
+            }
+        }
+
+        /// Starts a https server on `localhost`, which requires a client certificate when
+        /// `mutual` is set, answering each request with whether the client presented one.
+        async fn serve(&self, mutual: bool) -> String {
+            let (certificate, key) = &self.server;
+            serve(vec![certificate.clone()], key, mutual.then_some(self.ca.as_str())).await
+        }
+    }
+
+    /// Starts a https server on `localhost` sending `chain`, which requires a client certificate
+    /// issued by `client_ca` when it is set, answering each request with whether the client
+    /// presented one.
+    async fn serve(chain: Vec<CertificateDer<'static>>, key: &KeyPair, client_ca: Option<&str>) -> String {
+        let key = PrivateKeyDer::from_pem_slice(key.serialize_pem().as_bytes()).unwrap();
+        let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap();
+        let builder = if let Some(client_ca) = client_ca {
+            let mut roots = RootCertStore::empty();
+            roots.add_parsable_certificates(parse_certificates(client_ca.as_bytes()).unwrap());
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .unwrap();
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+        let config = builder.with_single_cert(chain, key).unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://localhost:{}/", listener.local_addr().unwrap().port());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(stream).await else { return };
+                    let mut head = Vec::new();
+                    while !head.ends_with(b"\r\n\r\n") {
+                        let mut byte = [0];
+                        if stream.read_exact(&mut byte).await.is_err() {
+                            return;
+                        }
+                        head.push(byte[0]);
+                    }
+                    let presented = stream.get_ref().1.peer_certificates().is_some();
+                    let body = if presented { "client certificate" } else { "anonymous" };
+                    let _ = stream.write_all(&response(200, &[], body.as_bytes())).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+        url
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn private_roots_and_client_certificates() {
+        let authority = Authority::new();
+        let url = authority.serve(true).await;
+        let (certificate, key) = &authority.client;
+        let tls = TlsOptions::new()
+            .root_certificate_pem(authority.ca.as_bytes())
+            .client_certificate_pem(certificate.as_bytes(), key.as_bytes())
+            .min_version(TlsVersion::Tls1_3);
+        let response = fetch_with(&url, RequestInit { tls: Some(tls), ..Default::default() }).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "client certificate");
+        let tls = TlsOptions::new().root_certificate_pem(authority.ca.as_bytes());
+        let client = FetchClient::builder().tls(tls).build().unwrap();
+        assert!(client.fetch(&url).await.is_err());
+        let error = fetch_with(&url, RequestInit::default()).await.unwrap_err();
+        assert_eq!(error.name(), "NetworkError");
+        let tls = TlsOptions::new().client_certificate_pem(b"not a certificate", key.as_bytes());
+        assert!(matches!(FetchClient::builder().tls(tls).build(), Err(Error::TypeError { .. })));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pins_are_checked_even_for_invalid_certificates() {
+        let authority = Authority::new();
+        let url = authority.serve(false).await;
+        let (certificate, key) = &authority.server;
+        let spki_hash: [u8; 32] = Sha256::digest(key.subject_public_key_info()).into();
+        assert_eq!(spki(certificate), Some(&key.subject_public_key_info()[..]));
+        let fetch_text = |tls: TlsOptions| {
+            let client = FetchClient::builder().tls(tls).build().unwrap();
+            let url = url.clone();
+            async move { client.fetch(&url).await?.text().await }
+        };
+        let roots = TlsOptions::new().root_certificate_pem(authority.ca.as_bytes());
+        assert_eq!(fetch_text(roots.clone().pin_spki_sha256(spki_hash)).await.unwrap(), "anonymous");
+        assert!(fetch_text(roots.pin_certificate_sha256([0; 32])).await.is_err());
+        let any = TlsOptions::new().danger_accept_invalid_certs(true);
+        assert_eq!(fetch_text(any.clone()).await.unwrap(), "anonymous");
+        let certificate_hash: [u8; 32] = Sha256::digest(certificate).into();
+        assert!(fetch_text(any.clone().pin_certificate_sha256(certificate_hash)).await.is_ok());
+        assert!(fetch_text(any.pin_spki_sha256([0; 32])).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pins_only_match_the_verified_chain() {
+        let (pinned, attacker) = (Authority::new(), Authority::new());
+        let ca_hash: [u8; 32] = Sha256::digest(&pinned.ca_der).into();
+        let fetch_text = |url: String, tls: TlsOptions| async move {
+            FetchClient::builder().tls(tls).build().unwrap().fetch(&url).await?.text().await
+        };
+        // The root the chain is built to is pinned, though the server does not send it.
+        let url = pinned.serve(false).await;
+        let roots = TlsOptions::new().root_certificate_pem(pinned.ca.as_bytes());
+        let tls = roots.clone().pin_certificate_sha256(ca_hash);
+        assert_eq!(fetch_text(url, tls).await.unwrap(), "anonymous");
+        // A trusted attacker appends the pinned certificates to the chain of its own certificate.
+        let (certificate, key) = &attacker.server;
+        let chain = vec![certificate.clone(), pinned.ca_der.clone(), pinned.server.0.clone()];
+        let url = serve(chain, key, None).await;
+        let roots = roots.root_certificate_pem(attacker.ca.as_bytes());
+        assert_eq!(fetch_text(url.clone(), roots.clone()).await.unwrap(), "anonymous");
+        assert!(fetch_text(url.clone(), roots.pin_certificate_sha256(ca_hash)).await.is_err());
+        let server_hash: [u8; 32] = Sha256::digest(&pinned.server.0).into();
+        let tls = TlsOptions::new().danger_accept_invalid_certs(true).pin_certificate_sha256(server_hash);
+        assert!(fetch_text(url, tls).await.is_err());
+    }
+}
@@ -0,0 +1,80 @@
+//! The `Transport` which sends each request of a `fetch` over the network.
+
+use crate::request::SourceError;
+use crate::{headers, Error, Request, Response, Result};
+use futures_util::future::BoxFuture;
+use std::fmt;
+use std::sync::Arc;
+
+/// Sends a single http request and receives its response, without following redirects.
+///
+/// A `FetchClient` sends its requests over the network by default. Another transport can be
+/// set with `FetchClientBuilder::transport`, like a `MockFetch` in tests. Redirects, cookies,
+/// the http cache and retries are all handled before a request reaches the transport.
+///
+/// # Examples
+///
+/// ```rust
+/// use futures_util::future::BoxFuture;
+/// use js_lib::{Request, Response, ResponseInit, Transport};
+///
+/// #[derive(Debug)]
+/// struct Teapot;
+///
+/// impl Transport for Teapot {
+///     fn send(&self, request: Request) -> BoxFuture<'static, js_lib::Result<Response>> {
+///         Box::pin(async move {
+///             Ok(Response::new("I'm a teapot", ResponseInit { status: 418, ..Default::default() }))
+///         })
+///     }
+/// }
+/// ```
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Sends `request`, resolving to its response once the status and headers are received.
+    fn send(&self, request: Request) -> BoxFuture<'static, Result<Response>>;
+}
+
+/// The transport sending requests over the network with a pooled reqwest client.
+#[derive(Debug, Clone)]
+pub(crate) struct HttpTransport {
+    client: reqwest::Client
+}
+
+impl HttpTransport {
+    pub(crate) fn new(client: reqwest::Client) -> HttpTransport {
+        HttpTransport { client }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send(&self, mut request: Request) -> BoxFuture<'static, Result<Response>> {
+        let mut builder = self.client.request(request.method().clone(), request.url());
+        for (name, value) in request.headers().raw() {
+            builder = builder.header(name.as_str(), headers::encode_value(value));
+        }
+        let source_error = SourceError::default();
+        if let Some(body) = request.take_body() {
+            builder = builder.body(body.into_reqwest(&source_error));
+        }
+        Box::pin(async move {
+            match builder.send().await {
+                Ok(response) => Ok(Response::from_reqwest(response)),
+                Err(error) => Err(source_error.take().unwrap_or_else(|| Error::from(error)))
+            }
+        })
+    }
+}
+
+tokio::task_local! {
+    static SCOPED: Arc<dyn Transport>;
+}
+
+/// Runs `future` with every `fetch` it makes sent by `transport`, whichever client makes it.
+pub(crate) async fn scope<F: std::future::Future>(transport: Arc<dyn Transport>, future: F) -> F::Output {
+    SCOPED.scope(transport, future).await
+}
+
+/// The transport set by the enclosing `scope`, if any.
+pub(crate) fn scoped() -> Option<Arc<dyn Transport>> {
+    SCOPED.try_with(Arc::clone).ok()
+}
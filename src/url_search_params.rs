@@ -0,0 +1,181 @@
+//! The javascript-like `URLSearchParams` of query strings and form bodies.
+
+use crate::{entries, Body, Error, Result};
+use bytes::Bytes;
+use std::fmt;
+
+/// A list of name and value pairs in `application/x-www-form-urlencoded` form, mirroring the
+/// javascript `URLSearchParams`.
+///
+/// Used as a request body, the pairs are sent with the matching `content-type`. They can also be
+/// attached to a url as its query string with `apply_to`.
+///
+/// # Examples
+///
+/// ```rust
+/// use js_lib::URLSearchParams;
+/// # fn example() -> Result<(), js_lib::Error> {
+/// let mut params = URLSearchParams::from("?q=rust+lang&page=1");
+/// params.append("tag", "a&b");
+/// assert_eq!(params.get("q"), Some("rust lang"));
+/// assert_eq!(params.to_string(), "q=rust+lang&page=1&tag=a%26b");
+/// let url = params.apply_to("https://example.com/search")?;
+/// assert_eq!(url, "https://example.com/search?q=rust+lang&page=1&tag=a%26b");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct URLSearchParams {
+    list: Vec<(String, String)>
+}
+
+impl URLSearchParams {
+    /// Creates an empty list of pairs.
+    pub fn new() -> URLSearchParams {
+        URLSearchParams::default()
+    }
+
+    /// Appends a pair, keeping any pairs which already have the same name.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.list.push((name.to_string(), value.to_string()));
+    }
+
+    /// Sets a pair, replacing every pair which already has the same name.
+    pub fn set(&mut self, name: &str, value: &str) {
+        entries::set(&mut self.list, name, value.to_string());
+    }
+
+    /// Gets the value of the first pair named `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).into_iter().next()
+    }
+
+    /// Gets the values of every pair named `name`, in order.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.list.iter().filter(|(key, _)| key == name).map(|(_, value)| value.as_str()).collect()
+    }
+
+    /// Whether a pair named `name` is present.
+    pub fn has(&self, name: &str) -> bool {
+        self.list.iter().any(|(key, _)| key == name)
+    }
+
+    /// Removes every pair named `name`.
+    pub fn delete(&mut self, name: &str) {
+        self.list.retain(|(key, _)| key != name);
+    }
+
+    /// Sorts the pairs by name, keeping the relative order of pairs with the same name.
+    pub fn sort(&mut self) {
+        self.list.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+    }
+
+    /// The number of pairs.
+    pub fn size(&self) -> usize {
+        self.list.len()
+    }
+
+    /// Iterates the pairs in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.list.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Sets the pairs as the query string of `url`, replacing any query it already has.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - `url` is not a valid absolute url
+    pub fn apply_to(&self, url: &str) -> Result<String> {
+        let mut url = match reqwest::Url::parse(url) {
+            Ok(url) => url,
+            Err(error) => return Err(Error::type_error(format!("invalid url {:?}: {}", url, error)))
+        };
+        if self.list.is_empty() {
+            url.set_query(None);
+        } else {
+            url.set_query(Some(&self.to_string()));
+        }
+        Ok(url.to_string())
+    }
+}
+
+impl fmt::Display for URLSearchParams {
+    /// Serializes the pairs as `application/x-www-form-urlencoded`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut serializer = form_urlencoded::Serializer::new(String::new());
+        serializer.extend_pairs(&self.list);
+        f.write_str(&serializer.finish())
+    }
+}
+
+impl From<&str> for URLSearchParams {
+    /// Parses a query string, ignoring a leading `?`.
+    fn from(query: &str) -> Self {
+        let query = query.strip_prefix('?').unwrap_or(query);
+        form_urlencoded::parse(query.as_bytes()).collect()
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for URLSearchParams
+    where K: AsRef<str>, V: AsRef<str> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut params = URLSearchParams::new();
+        for (name, value) in iter {
+            params.append(name.as_ref(), value.as_ref());
+        }
+        params
+    }
+}
+
+impl<K, V, const N: usize> From<[(K, V); N]> for URLSearchParams
+    where K: AsRef<str>, V: AsRef<str> {
+    fn from(pairs: [(K, V); N]) -> Self {
+        pairs.into_iter().collect()
+    }
+}
+
+impl From<URLSearchParams> for Body {
+    fn from(params: URLSearchParams) -> Self {
+        let content_type = "application/x-www-form-urlencoded;charset=UTF-8".to_string();
+        Body::with_content_type(Bytes::from(params.to_string()), content_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{response, TestServer};
+    use crate::{fetch_with, Method, RequestInit};
+
+    #[test]
+    fn parse_sort_and_serialize() {
+        let mut params = URLSearchParams::from("b=2&a=%F0%9F%A6%80&b=1&c&=empty");
+        assert_eq!(params.get_all("b"), ["2", "1"]);
+        assert_eq!(params.get("a"), Some("🦀"));
+        assert_eq!(params.get("c"), Some(""));
+        params.sort();
+        let pairs = params.iter().collect::<Vec<_>>();
+        assert_eq!(pairs, [("", "empty"), ("a", "🦀"), ("b", "2"), ("b", "1"), ("c", "")]);
+        params.set("b", "x y");
+        params.delete("");
+        assert_eq!(params.to_string(), "a=%F0%9F%A6%80&b=x+y&c=");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn post_form_and_query() {
+        let server = TestServer::start(|_| response(200, &[], b"")).await;
+        let params = URLSearchParams::from([("user", "js lib"), ("lang", "rust")]);
+        let url = URLSearchParams::from([("page", "2")]).apply_to(&server.url("/login?old=1")).unwrap();
+        fetch_with(&url, RequestInit {
+            method: Method::POST,
+            body: Some(params.into()),
+            ..Default::default()
+        }).await.unwrap();
+        let received = &server.received()[0];
+        assert_eq!(received.target, "/login?page=2");
+        assert_eq!(received.header("content-type"), Some("application/x-www-form-urlencoded;charset=UTF-8"));
+        assert_eq!(received.body, b"user=js+lib&lang=rust");
+    }
+}
@@ -0,0 +1,770 @@
+//! The javascript-like `WebSocket`, a two-way connection exchanging messages with a server.
+
+use crate::{Error, Headers, Method, Result, TlsOptions};
+use bytes::Bytes;
+use futures_util::{SinkExt, Stream, StreamExt};
+use reqwest::Url;
+use rustls::pki_types::ServerName;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, Permit};
+use tokio::sync::Notify;
+use tokio::time::MissedTickBehavior;
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::WebSocketStream;
+
+/// The close code of a connection which was lost without a close frame.
+const ABNORMAL_CLOSURE: u16 = 1006;
+
+/// The most messages waiting to be sent, and the most events waiting to be read.
+const BUFFER_SIZE: usize = 16;
+
+/// The characters a subprotocol name can not contain, the separators of RFC 2616.
+const SEPARATORS: &[u8] = b"()<>@,;:\\\"/[]?={}";
+
+/// A connection exchanging text and binary messages with a server, mirroring the javascript
+/// `WebSocket`.
+///
+/// The connection is opened in the background as soon as the socket is created, so messages can
+/// only be sent once it is open. Its events, `WebSocketEvent::Open`, each message, errors and the
+/// final `WebSocketEvent::Close`, are read as a `Stream`, and passed to the `onopen`,
+/// `onmessage`, `onerror` and `onclose` hooks as they happen.
+///
+/// Only a few messages and events are buffered each way. The socket is not read while the events
+/// are not, so the stream must be read for messages to keep arriving even when hooks are used,
+/// and `WebSocket::send` waits while the messages sent before it are still being written.
+///
+/// `ws://` and `wss://` urls are supported, and `http://` and `https://` urls are connected to
+/// as their websocket equivalent. The connection does not go through a proxy.
+///
+/// # Examples
+///
+/// ```rust
+/// use futures_util::StreamExt;
+/// use js_lib::{WebSocket, WebSocketEvent};
+/// # async fn example() -> Result<(), js_lib::Error> {
+/// let mut socket = WebSocket::new("wss://example.com/chat")?;
+/// while let Some(event) = socket.next().await {
+///     match event {
+///         WebSocketEvent::Open => socket.send("hello").await?,
+///         WebSocketEvent::Message(message) => println!("{:?}", message),
+///         WebSocketEvent::Close(close) => println!("closed with {}", close.code),
+///         WebSocketEvent::Error(error) => println!("{}", error)
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct WebSocket {
+    url: String,
+    shared: Arc<Shared>,
+    messages: mpsc::Sender<WebSocketMessage>,
+    events: mpsc::Receiver<WebSocketEvent>
+}
+
+/// The options of a `WebSocket`, as the `protocols` argument of the javascript constructor.
+#[derive(Debug, Clone, Default)]
+pub struct WebSocketInit {
+    /// The subprotocols offered to the server, in order of preference, of which the server picks
+    /// one, e.g. `vec!["graphql-transport-ws".to_string()]`.
+    pub protocols: Vec<String>,
+    /// The headers sent with the opening handshake.
+    pub headers: Headers,
+    /// The TLS options of a `wss://` connection.
+    pub tls: Option<TlsOptions>,
+    /// The interval a ping is sent at, failing the connection when no frame was received since the
+    /// last ping. No pings are sent by default.
+    pub ping_interval: Option<Duration>
+}
+
+/// An event of a `WebSocket`.
+#[derive(Debug)]
+pub enum WebSocketEvent {
+    /// The connection was opened.
+    Open,
+    /// A message was received.
+    Message(WebSocketMessage),
+    /// The connection failed, and is closed next.
+    Error(Error),
+    /// The connection was closed, which is the last event.
+    Close(CloseEvent)
+}
+
+/// A message sent or received by a `WebSocket`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebSocketMessage {
+    /// A text message.
+    Text(String),
+    /// A binary message.
+    Binary(Bytes)
+}
+
+/// How the connection of a `WebSocket` was closed, mirroring the javascript `CloseEvent`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseEvent {
+    /// The close code, `1006` when the connection was lost without a close frame, or `1005` when
+    /// the close frame had no code.
+    pub code: u16,
+    /// The reason of the close frame.
+    pub reason: String,
+    /// Whether the close frames were exchanged before the connection was closed.
+    pub was_clean: bool
+}
+
+/// The state of the connection of a `WebSocket`, mirroring its javascript `readyState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebSocketState {
+    /// The connection is being opened.
+    Connecting,
+    /// The connection is open, and messages can be sent.
+    Open,
+    /// The close frames are being exchanged.
+    Closing,
+    /// The connection is closed.
+    Closed
+}
+
+type EventListener<T> = Arc<dyn Fn(&T) + Send + Sync>;
+
+/// The state a `WebSocket` shares with the task running its connection.
+struct Shared {
+    state: Mutex<WebSocketState>,
+    protocol: Mutex<String>,
+    listeners: Mutex<Listeners>,
+    close: Mutex<Option<(u16, String)>>,
+    close_requested: Notify
+}
+
+#[derive(Default)]
+struct Listeners {
+    open: Option<Arc<dyn Fn() + Send + Sync>>,
+    message: Option<EventListener<WebSocketMessage>>,
+    error: Option<EventListener<Error>>,
+    close: Option<EventListener<CloseEvent>>
+}
+
+/// The plain or TLS stream of a connection.
+trait Socket: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Socket for T {}
+
+impl WebSocket {
+    /// Opens a connection to `url`.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - `url` is not a valid `ws://`, `wss://`, `http://` or `https://` url, or has a fragment
+    ///
+    /// The errors of the connection are `WebSocketEvent::Error` events instead.
+    ///
+    /// # Panics
+    ///
+    /// This function panics when it is not called from a tokio runtime.
+    pub fn new(url: &str) -> Result<WebSocket> {
+        WebSocket::with_init(url, WebSocketInit::default())
+    }
+
+    /// Opens a connection to `url` with the options of `init`.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - `url` is not a valid `ws://`, `wss://`, `http://` or `https://` url, or has a fragment
+    /// - `init.protocols` has an invalid or repeated subprotocol
+    ///
+    /// The errors of the connection are `WebSocketEvent::Error` events instead.
+    ///
+    /// # Panics
+    ///
+    /// This function panics when it is not called from a tokio runtime.
+    pub fn with_init(url: &str, init: WebSocketInit) -> Result<WebSocket> {
+        let url = parse_url(url)?;
+        for (index, protocol) in init.protocols.iter().enumerate() {
+            let valid = protocol.bytes().all(|byte| byte.is_ascii_graphic() && !SEPARATORS.contains(&byte));
+            if protocol.is_empty() || !valid || init.protocols[..index].contains(protocol) {
+                return Err(Error::type_error(format!("invalid or repeated subprotocol {:?}", protocol)));
+            }
+        }
+        let shared = Arc::new(Shared {
+            state: Mutex::new(WebSocketState::Connecting),
+            protocol: Mutex::new(String::new()),
+            listeners: Mutex::new(Listeners::default()),
+            close: Mutex::new(None),
+            close_requested: Notify::new()
+        });
+        let (messages, message_receiver) = mpsc::channel(BUFFER_SIZE);
+        let (event_sender, events) = mpsc::channel(BUFFER_SIZE);
+        let connection = Connection { url: url.clone(), init, shared: shared.clone(), events: event_sender };
+        tokio::spawn(connection.run(message_receiver));
+        Ok(WebSocket { url: url.to_string(), shared, messages, events })
+    }
+
+    /// The url of the connection, with a `ws://` or `wss://` scheme.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The subprotocol picked by the server, or an empty string until the connection is open or
+    /// when none was offered.
+    pub fn protocol(&self) -> String {
+        self.shared.protocol.lock().unwrap().clone()
+    }
+
+    /// The state of the connection.
+    pub fn ready_state(&self) -> WebSocketState {
+        *self.shared.state.lock().unwrap()
+    }
+
+    /// Sends a text or binary message, e.g. `socket.send("hello")` or `socket.send(vec![1, 2])`,
+    /// waiting while the buffer of messages to be written is full.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - the connection is not open
+    pub async fn send(&self, message: impl Into<WebSocketMessage>) -> Result<()> {
+        if self.ready_state() != WebSocketState::Open {
+            return Err(Error::type_error(format!("the websocket is {:?}, not open", self.ready_state())));
+        }
+        match self.messages.send(message.into()).await {
+            Ok(()) => Ok(()),
+            Err(_) => Err(Error::type_error("the websocket is closed"))
+        }
+    }
+
+    /// Serializes `value` as json and sends it as a text message.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - `value` can not be serialized as json
+    /// - the connection is not open
+    pub async fn send_json<T>(&self, value: &T) -> Result<()>
+        where T: serde::Serialize + ?Sized {
+        match serde_json::to_string(value) {
+            Ok(json) => self.send(json).await,
+            Err(error) => Err(Error::from(error))
+        }
+    }
+
+    /// Closes the connection with the normal closure code `1000`.
+    pub fn close(&self) {
+        // The code and reason are valid, so this can not fail.
+        let _ = self.close_with(1000, "");
+    }
+
+    /// Closes the connection with a close code and reason, which the server echoes back.
+    ///
+    /// Closing a connection which is being opened closes it once it is open, without an
+    /// `WebSocketEvent::Open` event. Closing a closed connection does nothing.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - `code` is neither `1000` nor in the range `3000..=4999` of application codes
+    /// - `reason` is longer than 123 bytes
+    pub fn close_with(&self, code: u16, reason: &str) -> Result<()> {
+        if code != 1000 && !(3000..=4999).contains(&code) {
+            return Err(Error::type_error(format!("the close code {} is not 1000 or in 3000..=4999", code)));
+        }
+        if reason.len() > 123 {
+            return Err(Error::type_error("the close reason is longer than 123 bytes"));
+        }
+        {
+            let mut state = self.shared.state.lock().unwrap();
+            if matches!(*state, WebSocketState::Closing | WebSocketState::Closed) {
+                return Ok(());
+            }
+            *state = WebSocketState::Closing;
+        }
+        *self.shared.close.lock().unwrap() = Some((code, reason.to_string()));
+        self.shared.close_requested.notify_one();
+        Ok(())
+    }
+
+    /// Sets the hook called once the connection is open.
+    pub fn onopen(&mut self, hook: impl Fn() + Send + Sync + 'static) {
+        self.shared.listeners.lock().unwrap().open = Some(Arc::new(hook));
+    }
+
+    /// Sets the hook called with each message received.
+    pub fn onmessage(&mut self, hook: impl Fn(&WebSocketMessage) + Send + Sync + 'static) {
+        self.shared.listeners.lock().unwrap().message = Some(Arc::new(hook));
+    }
+
+    /// Sets the hook called with the error which failed the connection.
+    pub fn onerror(&mut self, hook: impl Fn(&Error) + Send + Sync + 'static) {
+        self.shared.listeners.lock().unwrap().error = Some(Arc::new(hook));
+    }
+
+    /// Sets the hook called once the connection is closed.
+    pub fn onclose(&mut self, hook: impl Fn(&CloseEvent) + Send + Sync + 'static) {
+        self.shared.listeners.lock().unwrap().close = Some(Arc::new(hook));
+    }
+}
+
+impl Stream for WebSocket {
+    type Item = WebSocketEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+impl fmt::Debug for WebSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("url", &self.url)
+            .field("protocol", &self.protocol())
+            .field("ready_state", &self.ready_state())
+            .finish()
+    }
+}
+
+impl WebSocketMessage {
+    /// Deserializes the text or bytes of the message as json into type T.
+    ///
+    /// # Errors
+    ///
+    /// This function fails if:
+    ///
+    /// - there is an error parsing the message as json
+    pub fn json<T>(&self) -> Result<T>
+        where T: serde::de::DeserializeOwned {
+        match self {
+            WebSocketMessage::Text(text) => crate::from_json(text),
+            WebSocketMessage::Binary(bytes) => match serde_json::from_slice(bytes) {
+                Ok(value) => Ok(value),
+                Err(error) => Err(Error::from(error))
+            }
+        }
+    }
+}
+
+impl From<&str> for WebSocketMessage {
+    fn from(text: &str) -> Self {
+        WebSocketMessage::Text(text.to_string())
+    }
+}
+
+impl From<String> for WebSocketMessage {
+    fn from(text: String) -> Self {
+        WebSocketMessage::Text(text)
+    }
+}
+
+impl From<Vec<u8>> for WebSocketMessage {
+    fn from(bytes: Vec<u8>) -> Self {
+        WebSocketMessage::Binary(Bytes::from(bytes))
+    }
+}
+
+impl From<Bytes> for WebSocketMessage {
+    fn from(bytes: Bytes) -> Self {
+        WebSocketMessage::Binary(bytes)
+    }
+}
+
+impl From<WebSocketMessage> for Message {
+    fn from(message: WebSocketMessage) -> Self {
+        match message {
+            WebSocketMessage::Text(text) => Message::text(text),
+            WebSocketMessage::Binary(bytes) => Message::Binary(bytes)
+        }
+    }
+}
+
+impl CloseEvent {
+    fn abnormal() -> CloseEvent {
+        CloseEvent { code: ABNORMAL_CLOSURE, reason: String::new(), was_clean: false }
+    }
+}
+
+/// Parses the url of a `WebSocket`, replacing a `http` scheme with `ws`.
+fn parse_url(url: &str) -> Result<Url> {
+    let mut parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(error) => return Err(Error::type_error(format!("invalid websocket url {:?}: {}", url, error)))
+    };
+    let scheme = match parsed.scheme() {
+        "http" | "ws" => "ws",
+        "https" | "wss" => "wss",
+        scheme => return Err(Error::type_error(format!("the websocket scheme {:?} is not supported", scheme)))
+    };
+    // The scheme is special in both, so the change can not fail.
+    let _ = parsed.set_scheme(scheme);
+    if parsed.fragment().is_some() || parsed.host_str().is_none() {
+        return Err(Error::type_error(format!("the websocket url {:?} has a fragment or no host", url)));
+    }
+    Ok(parsed)
+}
+
+/// The connection of a `WebSocket`, run by a task of its own.
+struct Connection {
+    url: Url,
+    init: WebSocketInit,
+    shared: Arc<Shared>,
+    events: mpsc::Sender<WebSocketEvent>
+}
+
+impl Connection {
+    async fn run(self, mut messages: mpsc::Receiver<WebSocketMessage>) {
+        let (close, error) = match self.connect().await {
+            Ok(socket) => {
+                let opened = {
+                    let mut state = self.shared.state.lock().unwrap();
+                    let opened = *state == WebSocketState::Connecting;
+                    if opened {
+                        *state = WebSocketState::Open;
+                    }
+                    opened
+                };
+                if opened {
+                    let hook = self.shared.listeners.lock().unwrap().open.clone();
+                    if let Some(hook) = hook {
+                        hook();
+                    }
+                    let _ = self.events.send(WebSocketEvent::Open).await;
+                }
+                self.exchange(socket, &mut messages).await
+            },
+            Err(error) => (CloseEvent::abnormal(), Some(error))
+        };
+        // A send waiting for room fails now, rather than once the last events are read.
+        drop(messages);
+        if let Some(error) = error {
+            self.fail(error).await;
+        }
+        *self.shared.state.lock().unwrap() = WebSocketState::Closed;
+        let hook = self.shared.listeners.lock().unwrap().close.clone();
+        if let Some(hook) = hook {
+            hook(&close);
+        }
+        let _ = self.events.send(WebSocketEvent::Close(close)).await;
+    }
+
+    /// Opens the TCP and TLS connection, and makes the opening handshake.
+    async fn connect(&self) -> Result<WebSocketStream<Box<dyn Socket>>> {
+        // An ipv6 host is connected to without the brackets of the url.
+        let host = self.url.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+        let port = self.url.port_or_known_default().unwrap_or(80);
+        let tcp = TcpStream::connect((host, port)).await?;
+        tcp.set_nodelay(true)?;
+        let stream: Box<dyn Socket> = if self.url.scheme() == "wss" {
+            let mut config = self.init.tls.clone().unwrap_or_default().client_config()?;
+            // The upgrade is a http/1.1 request, which h2 does not have.
+            config.alpn_protocols = vec![b"http/1.1".to_vec()];
+            let server_name = match ServerName::try_from(host.to_string()) {
+                Ok(server_name) => server_name,
+                Err(error) => {
+                    return Err(Error::type_error(format!("invalid server name {:?}: {}", host, error)));
+                }
+            };
+            Box::new(TlsConnector::from(Arc::new(config)).connect(server_name, tcp).await?)
+        } else {
+            Box::new(tcp)
+        };
+        let mut request = self.url.as_str().into_client_request()?;
+        for (name, value) in self.init.headers.raw() {
+            match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                (Ok(name), Ok(value)) => request.headers_mut().append(name, value),
+                _ => return Err(Error::type_error(format!("invalid header {}: {:?}", name, value)))
+            };
+        }
+        if !self.init.protocols.is_empty() {
+            // The subprotocols were validated, so they are a valid header value.
+            let protocols = HeaderValue::from_str(&self.init.protocols.join(", ")).unwrap();
+            request.headers_mut().insert("sec-websocket-protocol", protocols);
+        }
+        let (socket, response) = match tokio_tungstenite::client_async(request, stream).await {
+            Ok(connected) => connected,
+            Err(tungstenite::Error::Http(response)) => {
+                let body = response.body().as_deref().unwrap_or_default();
+                let snippet = String::from_utf8_lossy(body).into_owned();
+                return Err(Error::http_status(response.status().as_u16(), self.url.to_string(), snippet));
+            },
+            Err(error) => return Err(Error::from(error))
+        };
+        if let Some(protocol) = response.headers().get("sec-websocket-protocol") {
+            *self.shared.protocol.lock().unwrap() = protocol.to_str().unwrap_or_default().to_string();
+        }
+        Ok(socket)
+    }
+
+    /// Sends and receives messages until the connection is closed, returning how it was closed
+    /// and the error which failed it, if any.
+    async fn exchange(
+        &self,
+        mut socket: WebSocketStream<Box<dyn Socket>>,
+        messages: &mut mpsc::Receiver<WebSocketMessage>
+    ) -> (CloseEvent, Option<Error>) {
+        let mut ping = self.init.ping_interval.map(|interval| {
+            let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+            ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            ping
+        });
+        let mut awaiting_pong = false;
+        let mut close = None;
+        // The room for the event of the next message, as a frame is only read once it has some.
+        let mut permit = None;
+        loop {
+            let tick = async {
+                match &mut ping {
+                    Some(ping) => ping.tick().await,
+                    None => std::future::pending().await
+                }
+            };
+            let sent = tokio::select! {
+                reserved = self.events.reserve(), if permit.is_none() => match reserved {
+                    Ok(reserved) => {
+                        permit = Some(reserved);
+                        // A pong which was not read while the events were not is not the server's fault.
+                        awaiting_pong = false;
+                        Ok(())
+                    },
+                    // The `WebSocket` was dropped, so nothing is listening to the close event.
+                    Err(_) => {
+                        let _ = socket.close(None).await;
+                        return (CloseEvent::abnormal(), None);
+                    }
+                },
+                message = messages.recv() => match message {
+                    Some(message) => socket.send(message.into()).await,
+                    None => {
+                        let _ = socket.close(None).await;
+                        return (CloseEvent::abnormal(), None);
+                    }
+                },
+                _ = self.shared.close_requested.notified() => {
+                    // The messages sent before closing are written before the close frame.
+                    let mut sent = Ok(());
+                    while let Ok(message) = messages.try_recv() {
+                        sent = socket.send(message.into()).await;
+                        if sent.is_err() {
+                            break;
+                        }
+                    }
+                    let requested = self.shared.close.lock().unwrap().take();
+                    let (code, reason) = requested.unwrap_or((1000, String::new()));
+                    match sent {
+                        Ok(()) => {
+                            let frame = CloseFrame { code: code.into(), reason: reason.into() };
+                            socket.close(Some(frame)).await
+                        },
+                        Err(error) => Err(error)
+                    }
+                },
+                message = socket.next(), if permit.is_some() => {
+                    // Any frame shows the connection is alive, like the pong of the last ping.
+                    awaiting_pong = false;
+                    match message {
+                        Some(Ok(Message::Text(text))) => {
+                            self.receive(&mut permit, WebSocketMessage::Text(text.to_string()))
+                        },
+                        Some(Ok(Message::Binary(bytes))) => {
+                            self.receive(&mut permit, WebSocketMessage::Binary(bytes))
+                        },
+                        Some(Ok(Message::Close(frame))) => {
+                            self.set_closing();
+                            let (code, reason) = match frame {
+                                Some(frame) => (frame.code.into(), frame.reason.to_string()),
+                                None => (1005, String::new())
+                            };
+                            close = Some(CloseEvent { code, reason, was_clean: true });
+                        },
+                        Some(Ok(_)) => {},
+                        Some(Err(tungstenite::Error::ConnectionClosed)) | None => {
+                            return (close.unwrap_or_else(CloseEvent::abnormal), None);
+                        },
+                        Some(Err(error)) => {
+                            return (close.unwrap_or_else(CloseEvent::abnormal), Some(Error::from(error)));
+                        }
+                    }
+                    Ok(())
+                },
+                _ = tick => {
+                    // While the events are not read, neither is the pong, so it is not waited for.
+                    if awaiting_pong && permit.is_some() {
+                        let message = "no pong was received within the ping interval".to_string();
+                        return (CloseEvent::abnormal(), Some(Error::timeout_error(message)));
+                    }
+                    awaiting_pong = true;
+                    socket.send(Message::Ping(Bytes::new())).await
+                }
+            };
+            match sent {
+                Ok(()) | Err(tungstenite::Error::ConnectionClosed) => {},
+                Err(error) => return (close.unwrap_or_else(CloseEvent::abnormal), Some(Error::from(error)))
+            }
+        }
+    }
+
+    /// Reports a received message, with the room reserved for its event.
+    fn receive(&self, permit: &mut Option<Permit<'_, WebSocketEvent>>, message: WebSocketMessage) {
+        let hook = self.shared.listeners.lock().unwrap().message.clone();
+        if let Some(hook) = hook {
+            hook(&message);
+        }
+        if let Some(permit) = permit.take() {
+            permit.send(WebSocketEvent::Message(message));
+        }
+    }
+
+    async fn fail(&self, error: Error) {
+        let error = error.with_request(&Method::GET, self.url.as_str());
+        let hook = self.shared.listeners.lock().unwrap().error.clone();
+        if let Some(hook) = hook {
+            hook(&error);
+        }
+        let _ = self.events.send(WebSocketEvent::Error(error)).await;
+    }
+
+    fn set_closing(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        if *state == WebSocketState::Open {
+            *state = WebSocketState::Closing;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+    /// Accepts one connection, picking the `chat.v1` subprotocol and echoing every message.
+    async fn echo_server() -> (String, tokio::task::JoinHandle<Option<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/chat", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut token = None;
+            // The error response of the callback is the type of tungstenite's `Callback` trait.
+            #[allow(clippy::result_large_err)]
+            let callback = |request: &Request, mut response: Response| {
+                let authorization = request.headers().get("authorization");
+                token = authorization.map(|value| value.to_str().unwrap().to_string());
+                let protocol = HeaderValue::from_static("chat.v1");
+                response.headers_mut().insert("sec-websocket-protocol", protocol);
+                Ok(response)
+            };
+            let mut socket = tokio_tungstenite::accept_hdr_async(stream, callback).await.unwrap();
+            while let Some(Ok(message)) = socket.next().await {
+                if message.is_text() || message.is_binary() {
+                    socket.send(message).await.unwrap();
+                }
+            }
+            token
+        });
+        (url, server)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn messages_are_echoed_and_closed_cleanly() {
+        let (url, server) = echo_server().await;
+        let init = WebSocketInit {
+            protocols: vec!["chat.v2".to_string(), "chat.v1".to_string()],
+            headers: Headers::from([("authorization", "Bearer token")]),
+            ..Default::default()
+        };
+        let mut socket = WebSocket::with_init(&url.replace("ws://", "http://"), init).unwrap();
+        assert_eq!(socket.url(), url);
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        socket.onmessage(move |message| log.lock().unwrap().push(message.clone()));
+        let mut messages = Vec::new();
+        while let Some(event) = socket.next().await {
+            match event {
+                WebSocketEvent::Open => {
+                    assert_eq!(socket.protocol(), "chat.v1");
+                    socket.send("hello").await.unwrap();
+                    socket.send(vec![1, 2, 3]).await.unwrap();
+                    socket.send_json(&serde_json::json!({ "n": 1 })).await.unwrap();
+                },
+                WebSocketEvent::Message(message) => {
+                    messages.push(message);
+                    if messages.len() == 3 {
+                        socket.close_with(4000, "done").unwrap();
+                    }
+                },
+                WebSocketEvent::Error(error) => panic!("{}", error),
+                WebSocketEvent::Close(close) => {
+                    assert_eq!(close, CloseEvent { code: 4000, reason: "done".to_string(), was_clean: true });
+                }
+            }
+        }
+        assert_eq!(messages[0], WebSocketMessage::Text("hello".to_string()));
+        assert_eq!(messages[1], WebSocketMessage::Binary(Bytes::from_static(&[1, 2, 3])));
+        assert_eq!(messages[2].json::<serde_json::Value>().unwrap()["n"], 1);
+        assert_eq!(*received.lock().unwrap(), messages);
+        assert_eq!(socket.ready_state(), WebSocketState::Closed);
+        assert_eq!(server.await.unwrap().as_deref(), Some("Bearer token"));
+        assert!(socket.close_with(1001, "").is_err());
+        assert!(WebSocket::new("ftp://example.com/").is_err());
+        let repeated = vec!["a".to_string(), "a".to_string()];
+        let repeated = WebSocketInit { protocols: repeated, ..Default::default() };
+        assert!(WebSocket::with_init("ws://example.com/", repeated).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn messages_sent_beyond_the_buffer_wait_for_the_events_to_be_read() {
+        let (url, _server) = echo_server().await;
+        let init = WebSocketInit { protocols: vec!["chat.v1".to_string()], ..Default::default() };
+        let mut socket = WebSocket::with_init(&url, init).unwrap();
+        assert!(matches!(socket.next().await, Some(WebSocketEvent::Open)));
+        // The echoes are not read while sending, so the buffers of both channels fill up.
+        for index in 0..100 {
+            socket.send(index.to_string()).await.unwrap();
+        }
+        for index in 0..100 {
+            match socket.next().await {
+                Some(WebSocketEvent::Message(message)) => {
+                    assert_eq!(message, WebSocketMessage::Text(index.to_string()));
+                },
+                event => panic!("{:?}", event)
+            }
+        }
+        socket.close();
+        let events = socket.collect::<Vec<_>>().await;
+        assert!(matches!(&events[..], [WebSocketEvent::Close(close)] if close.was_clean));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unanswered_pings_fail_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            // The socket is never read, so the pings are never answered.
+            let _socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        });
+        let init = WebSocketInit { ping_interval: Some(Duration::from_millis(50)), ..Default::default() };
+        let socket = WebSocket::with_init(&url, init).unwrap();
+        let events = socket.collect::<Vec<_>>().await;
+        assert!(matches!(events[0], WebSocketEvent::Open));
+        assert!(matches!(&events[1], WebSocketEvent::Error(Error::TimeoutError { .. })));
+        assert!(matches!(&events[2], WebSocketEvent::Close(close) if close.code == 1006 && !close.was_clean));
+        let refused = WebSocket::new(&url.replace(url.rsplit(':').next().unwrap(), "1/")).unwrap();
+        assert!(refused.send("never open").await.is_err());
+        let events = refused.collect::<Vec<_>>().await;
+        assert!(matches!(&events[0], WebSocketEvent::Error(Error::NetworkError { .. })));
+        assert!(matches!(&events[1], WebSocketEvent::Close(close) if close.code == 1006));
+    }
+}